@@ -1,9 +1,53 @@
-use anyhow::Context;
+use std::process::Stdio;
+
+use anyhow::{bail, Context};
 use tokio::process::Command;
 
-use crate::{environment::Environment, planner::DocumentChannels};
+use crate::{
+    environment::Environment,
+    planner::{Chapter, DocumentChannels},
+};
+
+fn ffmetadata_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace(';', "\\;")
+        .replace('#', "\\#")
+        .replace('\n', "\\\n")
+}
 
-fn to_filter_complex(doc: &DocumentChannels) -> String {
+fn to_ffmetadata(chapters: &[Chapter]) -> String {
+    chapters
+        .iter()
+        .fold(String::from(";FFMETADATA1\n"), |acc, chapter| {
+            format!(
+                "{acc}[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                (chapter.start * 1000.0).round() as u64,
+                (chapter.end * 1000.0).round() as u64,
+                ffmetadata_escape(&chapter.title),
+            )
+        })
+}
+
+/// Join `[{label}0]`, `[{label}1]`, ... into `[{label}]`, with `joins[n]` between n and n + 1
+///
+/// generates likes [v0][v1]xfade[vx1]; [vx1][v2]concat[vx2]; [vx2]null[v];
+fn join_pairwise(filter_complex: String, label: &str, null: &str, joins: &[String]) -> String {
+    let (filter_complex, last) = joins.iter().enumerate().fold(
+        (filter_complex, format!("{label}0")),
+        |(acc, prev), (n, join)| {
+            let n = n + 1;
+            (
+                format!("{acc}[{prev}][{label}{n}]{join}[{label}x{n}];\n"),
+                format!("{label}x{n}"),
+            )
+        },
+    );
+
+    format!("{filter_complex}[{last}]{null}[{label}];\n")
+}
+
+fn to_filter_complex(env: &Environment, doc: &DocumentChannels) -> String {
     let filter_complex = String::new();
 
     let filter_complex = doc
@@ -27,13 +71,39 @@ fn to_filter_complex(doc: &DocumentChannels) -> String {
         .enumerate()
         .fold(filter_complex, |acc, (n, v)| format!("{acc}{v}[v{n}];\n"));
 
-    // generate likes [v0][v1][v2] ...
-    let concat_targets = (0..doc.videos.len()).fold(String::new(), |acc, n| format!("{acc}[v{n}]"));
+    let filter_complex = if doc.video_transitions.iter().all(Option::is_none) {
+        // generate likes [v0][v1][v2] ...
+        let concat_targets =
+            (0..doc.videos.len()).fold(String::new(), |acc, n| format!("{acc}[v{n}]"));
 
-    let filter_complex = format!(
-        "{filter_complex}{concat_targets}concat=n={}:v=1:a=0[v];\n",
-        doc.videos.len()
-    );
+        format!(
+            "{filter_complex}{concat_targets}concat=n={}:v=1:a=0[v];\n",
+            doc.videos.len()
+        )
+    } else {
+        let joins: Vec<String> = doc
+            .video_transitions
+            .iter()
+            .map(|transition| match transition {
+                Some(t) => format!(
+                    "xfade=transition={}:duration={}:offset={}",
+                    t.effect, t.duration, t.offset
+                ),
+                None => String::from("concat=n=2:v=1:a=0"),
+            })
+            .collect();
+
+        join_pairwise(filter_complex, "v", "null", &joins)
+    };
+
+    let (filter_complex, video_label) = if doc.video_filters.is_empty() {
+        (filter_complex, "v")
+    } else {
+        (
+            format!("{filter_complex}[v]{}[vf];\n", doc.video_filters.join(",")),
+            "vf",
+        )
+    };
 
     let filter_complex = doc
         .bg_sounds
@@ -41,32 +111,66 @@ fn to_filter_complex(doc: &DocumentChannels) -> String {
         .enumerate()
         .fold(filter_complex, |acc, (n, v)| format!("{acc}{v}[bga{n}];\n"));
 
-    // generate likes [fga0][fga1][fga2] ...
-    let concat_targets =
-        (0..doc.bg_sounds.len()).fold(String::new(), |acc, n| format!("{acc}[bga{n}]"));
+    let filter_complex = if doc.bg_crossfades.iter().all(Option::is_none) {
+        // generate likes [bga0][bga1][bga2] ...
+        let concat_targets =
+            (0..doc.bg_sounds.len()).fold(String::new(), |acc, n| format!("{acc}[bga{n}]"));
 
-    let filter_complex = format!(
-        "{filter_complex}{concat_targets}concat=n={}:v=0:a=1[bga];\n",
-        doc.bg_sounds.len()
-    );
+        format!(
+            "{filter_complex}{concat_targets}concat=n={}:v=0:a=1[bga];\n",
+            doc.bg_sounds.len()
+        )
+    } else {
+        let joins: Vec<String> = doc
+            .bg_crossfades
+            .iter()
+            .map(|crossfade| match crossfade {
+                Some(d) => format!("acrossfade=d={d}:c1=tri:c2=tri"),
+                None => String::from("concat=n=2:v=0:a=1"),
+            })
+            .collect();
+
+        join_pairwise(filter_complex, "bga", "anull", &joins)
+    };
+
+    let limiter = match env.mixer_limit_db() {
+        // alimiter takes a linear limit in 0.0625..=1
+        Some(limit_db) => format!(
+            ",alimiter=limit={}:level=disabled",
+            10f64.powf(limit_db / 20.0).clamp(0.0625, 1.0)
+        ),
+        None => String::new(),
+    };
 
     let filter_complex = format!(
-        "{filter_complex}[bga][fga]amix[a];\n"
+        "{filter_complex}[bga]volume={}dB[bgam];\n[fga]volume={}dB[fgam];\n[bgam][fgam]amix=inputs=2:duration=longest:normalize={}:dropout_transition={}{limiter}[a];\n",
+        env.mixer_bg_gain_db(),
+        env.mixer_fg_gain_db(),
+        u8::from(env.mixer_normalize()),
+        env.mixer_dropout_transition(),
     );
 
-    format!("{filter_complex}\n[v][a]concat=n=1:v=1:a=1")
+    format!("{filter_complex}\n[{video_label}][a]concat=n=1:v=1:a=1")
 }
 
 pub async fn encode(env: &Environment, doc: &DocumentChannels) -> anyhow::Result<()> {
-    let filter_complex = to_filter_complex(doc);
+    let filter_complex = to_filter_complex(env, doc);
 
-    let mut a = vec![
-        "-nostdin",
-        "-hide_banner",
-        "-y",
-        "-filter_complex",
-        &filter_complex,
-    ];
+    let mut a = vec!["-nostdin", "-hide_banner", "-y"];
+
+    if !doc.chapters.is_empty() {
+        std::fs::write("chapters.ffmeta", to_ffmetadata(&doc.chapters))
+            .with_context(|| "Failed to write chapters.ffmeta")?;
+
+        #[rustfmt::skip]
+        a.extend([
+            "-f", "ffmetadata",
+            "-i", "chapters.ffmeta",
+            "-map_chapters", "0",
+        ]);
+    }
+
+    a.extend(["-filter_complex", &filter_complex]);
 
     let additional_args = env.ffmpeg_additional_args();
     a.extend(additional_args.iter().map(|s| s.as_str()));
@@ -101,3 +205,182 @@ pub async fn encode(env: &Environment, doc: &DocumentChannels) -> anyhow::Result
 
     Ok(())
 }
+
+/// Integrated loudness (LUFS) of the file, measured by loudnorm's analysis pass
+pub async fn measure_loudness(env: &Environment, file_path: &str) -> anyhow::Result<f64> {
+    #[rustfmt::skip]
+    let output = Command::new(env.ffmpeg_bin())
+        .args([
+            "-nostdin",
+            "-hide_banner",
+            "-i", file_path,
+            "-af", "loudnorm=print_format=json",
+            "-f", "null",
+            "-",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .with_context(|| "Failed to spawn ffmpeg")?;
+
+    if !output.status.success() {
+        match output.status.code() {
+            Some(code) => bail!("Exit code is not 0: {code}"),
+            None => bail!("Killed by signal"),
+        }
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let input_i = loudnorm_input_i(&stderr)
+        .with_context(|| format!("Failed to find input_i in loudnorm output: {file_path}"))?;
+
+    input_i
+        .parse()
+        .with_context(|| format!("Failed to parse loudnorm input_i as f64: {input_i}"))
+}
+
+/// The value of `"input_i" : "-23.45",` in the JSON loudnorm prints after the log
+fn loudnorm_input_i(stderr: &str) -> Option<&str> {
+    stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("\"input_i\""))
+        .and_then(|v| v.split('"').nth(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::planner::VideoTransition;
+
+    #[test]
+    fn ffmetadata_escapes_titles() {
+        assert_eq!(
+            ffmetadata_escape("a=b;c#d\\e\nf"),
+            "a\\=b\\;c\\#d\\\\e\\\nf"
+        );
+    }
+
+    #[test]
+    fn ffmetadata_chapters() {
+        let chapters = [
+            Chapter {
+                start: 0.0,
+                end: 1.2345,
+                title: String::from("Intro"),
+            },
+            Chapter {
+                start: 1.2345,
+                end: 3.0,
+                title: String::from("Q&A; #2"),
+            },
+        ];
+
+        assert_eq!(
+            to_ffmetadata(&chapters),
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=1235\ntitle=Intro\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=1235\nEND=3000\ntitle=Q&A\\; \\#2\n"
+        );
+    }
+
+    #[test]
+    fn join_pairwise_chains_the_joins() {
+        let joins = [
+            String::from("xfade=transition=fade:duration=1:offset=3"),
+            String::from("concat=n=2:v=1:a=0"),
+            String::from("xfade=transition=fade:duration=0.5:offset=7"),
+        ];
+
+        assert_eq!(
+            join_pairwise(String::from("head;\n"), "v", "null", &joins),
+            "head;\n\
+             [v0][v1]xfade=transition=fade:duration=1:offset=3[vx1];\n\
+             [vx1][v2]concat=n=2:v=1:a=0[vx2];\n\
+             [vx2][v3]xfade=transition=fade:duration=0.5:offset=7[vx3];\n\
+             [vx3]null[v];\n"
+        );
+    }
+
+    fn channels(video_transitions: Vec<Option<VideoTransition>>) -> DocumentChannels {
+        DocumentChannels {
+            videos: vec![String::from("color=c=red"); video_transitions.len() + 1],
+            fg_sounds: vec![String::from("anullsrc")],
+            bg_sounds: vec![String::from("anullsrc")],
+            bg_crossfades: vec![],
+            video_filters: vec![],
+            video_transitions,
+            chapters: vec![],
+        }
+    }
+
+    #[test]
+    fn hard_cut_between_transitions() {
+        let env = Environment::for_test(Path::new("/doc/main.md"));
+
+        let fade = VideoTransition {
+            effect: String::from("fade"),
+            duration: 1.0,
+            offset: 3.0,
+        };
+
+        let filter_complex = to_filter_complex(&env, &channels(vec![Some(fade), None]));
+
+        assert!(
+            filter_complex.contains(
+                "[v0][v1]xfade=transition=fade:duration=1:offset=3[vx1];\n\
+                 [vx1][v2]concat=n=2:v=1:a=0[vx2];\n\
+                 [vx2]null[v];\n"
+            ),
+            "{filter_complex}"
+        );
+    }
+
+    #[test]
+    fn loudnorm_output() {
+        let stderr = "Input #0, wav, from 'a.wav':\n\
+                      [Parsed_loudnorm_0 @ 0x5580] \n\
+                      {\n\
+                      \t\"input_i\" : \"-23.45\",\n\
+                      \t\"input_tp\" : \"-4.20\",\n\
+                      \t\"input_lra\" : \"1.30\",\n\
+                      }\n";
+
+        assert_eq!(loudnorm_input_i(stderr), Some("-23.45"));
+        assert_eq!(loudnorm_input_i("Conversion failed!\n"), None);
+    }
+
+    fn mix(mixer: &str) -> String {
+        let env = Environment::for_test_config(
+            Path::new("/doc/main.md"),
+            &format!("[profile.default.mixer]\n{mixer}"),
+        );
+
+        let filter_complex = to_filter_complex(&env, &channels(vec![]));
+        let start = filter_complex.find("[bga]volume=").unwrap();
+        let end = filter_complex.find("[a];").unwrap();
+
+        filter_complex[start..end].to_string()
+    }
+
+    #[test]
+    fn mixer_args() {
+        assert_eq!(
+            mix("fg_gain_db = 2.0\nbg_gain_db = -6.0\nnormalize = true\ndropout_transition_ms = 500\nlimiter = false\n"),
+            "[bga]volume=-6dB[bgam];\n[fga]volume=2dB[fgam];\n\
+             [bgam][fgam]amix=inputs=2:duration=longest:normalize=1:dropout_transition=0.5"
+        );
+    }
+
+    #[test]
+    fn limiter_limit_is_clamped() {
+        let limit = |mixer: &str| mix(mixer).split_once(",alimiter=").unwrap().1.to_string();
+
+        assert!(limit("").starts_with("limit=0.891"), "{}", limit(""));
+        assert_eq!(limit("limit_db = -30.0\n"), "limit=0.0625:level=disabled");
+        assert_eq!(limit("limit_db = 3.0\n"), "limit=1:level=disabled");
+    }
+}
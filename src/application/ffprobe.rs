@@ -5,17 +5,9 @@ use anyhow::{Context, bail};
 
 use crate::Environment;
 
-pub async fn measure_file_duration(env: &Environment, file_path: &str) -> anyhow::Result<f64> {
-    #[rustfmt::skip]
+async fn ffprobe(env: &Environment, args: &[&str]) -> anyhow::Result<String> {
     let mut child = Command::new(env.ffprobe_bin())
-        .args([
-            "-hide_banner",
-            "-v", "error",
-            "-select_streams", "a:0",
-            "-show_entries", "stream=duration",
-            "-of", "default=noprint_wrappers=1:nokey=1",
-            file_path,
-        ])
+        .args(args)
         .stdout(Stdio::piped())
         .spawn()
         .with_context(|| "Failed to spawn ffprobe")?;
@@ -38,11 +30,54 @@ pub async fn measure_file_duration(env: &Environment, file_path: &str) -> anyhow
         .with_context(|| "Failed to obtain stdout from ffprobe")?
         .stdout;
 
-    let stdout = std::str::from_utf8(&stdout)
+    Ok(std::str::from_utf8(&stdout)
         .with_context(|| "Failed to parse ffprobe output as UTF-8")?
-        .trim();
+        .trim()
+        .to_string())
+}
+
+pub async fn measure_file_duration(env: &Environment, file_path: &str) -> anyhow::Result<f64> {
+    #[rustfmt::skip]
+    let stdout = ffprobe(env, &[
+        "-hide_banner",
+        "-v", "error",
+        "-select_streams", "a:0",
+        "-show_entries", "stream=duration",
+        "-of", "default=noprint_wrappers=1:nokey=1",
+        file_path,
+    ]).await?;
 
     stdout
         .parse()
         .with_context(|| format!("Failed to parse ffprobe output as f64: {}", stdout))
 }
+
+/// Container duration; unlike `measure_file_duration` this works for files without audio
+pub async fn measure_media_duration(env: &Environment, file_path: &str) -> anyhow::Result<f64> {
+    #[rustfmt::skip]
+    let stdout = ffprobe(env, &[
+        "-hide_banner",
+        "-v", "error",
+        "-show_entries", "format=duration",
+        "-of", "default=noprint_wrappers=1:nokey=1",
+        file_path,
+    ]).await?;
+
+    stdout
+        .parse()
+        .with_context(|| format!("Failed to parse ffprobe output as f64: {}", stdout))
+}
+
+pub async fn has_audio_stream(env: &Environment, file_path: &str) -> anyhow::Result<bool> {
+    #[rustfmt::skip]
+    let stdout = ffprobe(env, &[
+        "-hide_banner",
+        "-v", "error",
+        "-select_streams", "a",
+        "-show_entries", "stream=index",
+        "-of", "default=noprint_wrappers=1:nokey=1",
+        file_path,
+    ]).await?;
+
+    Ok(!stdout.is_empty())
+}
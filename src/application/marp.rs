@@ -1,14 +1,24 @@
+use std::path::Path;
+
 use anyhow::{Context, bail};
 use tokio::process::Command;
 
 use crate::environment::Environment;
 
-pub fn count_generated_marp_pages() -> usize {
-    glob::glob("marp_doc.*").unwrap().count()
+/// Image name prefix of the `source`th Marp document (`marp_doc`, `marp_doc_1`, ...)
+pub fn marp_output(source: usize) -> String {
+    match source {
+        0 => String::from("marp_doc"),
+        n => format!("marp_doc_{n}"),
+    }
+}
+
+pub fn count_generated_marp_pages(output: &str) -> usize {
+    glob::glob(&format!("{output}.*")).unwrap().count()
 }
 
-pub async fn marp(env: &Environment) -> anyhow::Result<usize> {
-    for f in glob::glob("marp_doc.*").unwrap() {
+pub async fn marp(env: &Environment, md_path: &Path, output: &str) -> anyhow::Result<usize> {
+    for f in glob::glob(&format!("{output}.*")).unwrap() {
         let p = f.with_context(|| "Get marp doc path")?;
         std::fs::remove_file(p).with_context(|| "Failed to clean-up old marp_doc files")?
     }
@@ -16,17 +26,17 @@ pub async fn marp(env: &Environment) -> anyhow::Result<usize> {
     #[rustfmt::skip]
     let mut args = vec![
         "--images", "png",
-        "--output", "marp_doc",
+        "--output", output,
     ];
 
     let additional_args = env.marp_additional_args();
 
     args.extend(additional_args.iter().map(|v| v.as_str()));
-    args.push(&env.md_path().to_str().unwrap());
+    args.push(md_path.to_str().unwrap());
 
     println!("Args: marp {}", args.join(" ") );
 
-    let mut child = Command::new(&env.marp_bin())
+    let mut child = Command::new(env.marp_bin())
         .args(args)
         .envs(env.marp_envs())
         .spawn()
@@ -44,5 +54,5 @@ pub async fn marp(env: &Environment) -> anyhow::Result<usize> {
         }
     }
 
-    Ok(count_generated_marp_pages())
+    Ok(count_generated_marp_pages(output))
 }
@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use futures::future::BoxFuture;
@@ -6,13 +7,38 @@ use tokio::task::JoinError;
 
 use crate::application::*;
 use crate::environment::Environment;
-use crate::event::Event;
+use crate::event::{Bgm, Event};
 use crate::event_parser::*;
 
+type ProbeFuture<T> = BoxFuture<'static, Result<anyhow::Result<T>, JoinError>>;
+
 struct SoundFuture {
-    duration: BoxFuture<'static, Result<anyhow::Result<f64>, JoinError>>,
+    /// (duration, gain_db)
+    probe: ProbeFuture<(f64, f64)>,
+    path: PathBuf,
+    volume: f32,
+    text: Option<String>,
+}
+
+struct ClipFuture {
+    /// (duration, has_audio, gain_db)
+    probe: ProbeFuture<(f64, bool, f64)>,
     path: PathBuf,
     volume: f32,
+    start: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clip {
+    /// Trimmed duration
+    pub duration: f64,
+    pub path: PathBuf,
+    pub volume: f32,
+    pub start: f64,
+    pub has_audio: bool,
+
+    /// Loudness normalization gain
+    pub gain_db: f64,
 }
 
 #[derive(Debug, Clone)]
@@ -20,21 +46,71 @@ pub struct Sound {
     pub duration: f64,
     pub path: PathBuf,
     pub volume: f32,
+
+    /// Spoken text (voice only)
+    pub text: Option<String>,
+
+    /// Loudness normalization gain
+    pub gain_db: f64,
+}
+
+/// Gain which brings the file to the target loudness (0 dB if no target is configured)
+async fn loudness_gain(env: &Environment, path: &Path, target: Option<f64>) -> anyhow::Result<f64> {
+    let Some(target) = target else {
+        return Ok(0.0);
+    };
+
+    let cache = env.loudness_cache(path)?;
+
+    let measured: f64 = if std::fs::exists(&cache).with_context(|| "Checking cached loudness")? {
+        std::fs::read_to_string(&cache)?
+            .trim()
+            .parse()
+            .with_context(|| format!("Broken loudness cache: {}", cache.display()))?
+    } else {
+        let measured = measure_loudness(env, path.to_str().unwrap()).await?;
+
+        env.init_loudness_cache_dir()
+            .with_context(|| "Failed to create loudness cache directory")?;
+        std::fs::write(&cache, measured.to_string())?;
+
+        measured
+    };
+
+    // Silence can't be normalized
+    if !measured.is_finite() {
+        return Ok(0.0);
+    }
+
+    Ok(target - measured)
+}
+
+impl Event<Sound, Sound, Clip> {
+    /// Length of the event on the foreground timeline
+    pub fn duration(&self) -> Option<f64> {
+        match self {
+            Event::Voice(s) | Event::SoundEffect(s) => Some(s.duration),
+            Event::VPageMarker(c) => Some(c.duration),
+            Event::BlankMs(millis) => Some(*millis as f64 / 1000.0),
+            _ => None,
+        }
+    }
 }
 
 pub async fn prepare(
     env: &Environment,
-    events: &[Event<RawVoiceEvent, RawFgSoundEvent>],
-) -> anyhow::Result<Vec<Event<Sound, Sound>>> {
-    let mut event_future: Vec<Event<SoundFuture, SoundFuture>> = vec![];
+    events: &[Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>],
+) -> anyhow::Result<Vec<Event<Sound, Sound, Clip>>> {
+    let mut event_future: Vec<Event<SoundFuture, SoundFuture, ClipFuture>> = vec![];
+    let mut bgm_gains = VecDeque::new();
 
     for event in events {
         match event {
             Event::Voice(voice) => {
-                let filepath = env.voice_cache(&voice.profile, &voice.text);
+                let filepath = env.voice_cache(&voice.profile, voice.speech());
 
                 let handle = tokio::spawn({
-                    let text = voice.text.clone();
+                    let text = voice.speech().to_string();
                     let profile = voice.profile.clone();
                     let env = env.clone();
 
@@ -42,14 +118,20 @@ pub async fn prepare(
                         tts(&env, &profile, &text).await?;
 
                         let filepath = env.voice_cache(&profile, &text);
-                        measure_file_duration(&env, filepath.to_str().unwrap()).await
+                        let duration =
+                            measure_file_duration(&env, filepath.to_str().unwrap()).await?;
+                        let gain_db =
+                            loudness_gain(&env, &filepath, env.loudness_voice_target()).await?;
+
+                        Ok((duration, gain_db))
                     }
                 });
 
                 event_future.push(Event::Voice(SoundFuture {
                     path: filepath,
                     volume: 100.0,
-                    duration: Box::pin(handle),
+                    text: Some(voice.text.clone()),
+                    probe: Box::pin(handle),
                 }));
             }
             Event::SoundEffect(se) => {
@@ -57,30 +139,102 @@ pub async fn prepare(
                     let env = env.clone();
                     let path = se.path.clone();
 
-                    async move { measure_file_duration(&env, &path.to_str().unwrap()).await }
+                    async move {
+                        let duration = measure_file_duration(&env, path.to_str().unwrap()).await?;
+                        let gain_db =
+                            loudness_gain(&env, &path, env.loudness_sound_effect_target()).await?;
+
+                        Ok((duration, gain_db))
+                    }
                 });
 
                 event_future.push(Event::SoundEffect(SoundFuture {
                     path: se.path.clone(),
                     volume: se.volume,
-                    duration: Box::pin(handle),
+                    text: None,
+                    probe: Box::pin(handle),
                 }));
             }
-            Event::MVBGMMarker { path, volume } => {
-                event_future.push(Event::MVBGMMarker {
-                    path: path.clone(),
-                    volume: volume.clone(),
+            Event::VPageMarker(clip) => {
+                let start = clip.start.unwrap_or(0.0);
+
+                let handle = tokio::spawn({
+                    let env = env.clone();
+                    let path = clip.path.clone();
+                    let end = clip.end;
+
+                    async move {
+                        let path = path.to_str().unwrap();
+                        let file_duration = measure_media_duration(&env, path).await?;
+                        let has_audio = has_audio_stream(&env, path).await?;
+
+                        let end = end.unwrap_or(file_duration).min(file_duration);
+
+                        // Clips are mixed into the foreground channel like sound effects
+                        let gain_db = if has_audio {
+                            let target = env.loudness_sound_effect_target();
+                            loudness_gain(&env, Path::new(path), target).await?
+                        } else {
+                            0.0
+                        };
+
+                        Ok((end - start, has_audio, gain_db))
+                    }
                 });
+
+                event_future.push(Event::VPageMarker(ClipFuture {
+                    path: clip.path.clone(),
+                    volume: clip.volume,
+                    start,
+                    probe: Box::pin(handle),
+                }));
+            }
+            Event::MVBGMMarker(bgm) => {
+                let handle = tokio::spawn({
+                    let env = env.clone();
+                    let path = bgm.path.clone();
+
+                    async move {
+                        match path {
+                            Some(path) => {
+                                loudness_gain(&env, &path, env.loudness_bgm_target()).await
+                            }
+                            None => Ok(0.0),
+                        }
+                    }
+                });
+
+                bgm_gains.push_back(handle);
+                event_future.push(Event::MVBGMMarker(bgm.clone()));
             }
             Event::BlankMs(duration) => {
                 event_future.push(Event::BlankMs(*duration));
             }
-            Event::IPageMarker { path } => {
-                event_future.push(Event::IPageMarker { path: path.clone() });
+            Event::IPageMarker { path, ken_burns } => {
+                event_future.push(Event::IPageMarker {
+                    path: path.clone(),
+                    ken_burns: *ken_burns,
+                });
             }
-            Event::MPageMarker { marp_page_nth } => {
+            Event::MPageMarker {
+                source,
+                marp_page_nth,
+                title,
+            } => {
                 event_future.push(Event::MPageMarker {
+                    source: *source,
                     marp_page_nth: *marp_page_nth,
+                    title: title.clone(),
+                });
+            }
+            Event::ChapterMarker { title } => {
+                event_future.push(Event::ChapterMarker {
+                    title: title.clone(),
+                });
+            }
+            Event::TransitionMarker { transition } => {
+                event_future.push(Event::TransitionMarker {
+                    transition: transition.clone(),
                 });
             }
             Event::CPageMarker { color } => {
@@ -91,46 +245,95 @@ pub async fn prepare(
         }
     }
 
-    let mut events: Vec<Event<Sound, Sound>> = vec![];
+    let mut events: Vec<Event<Sound, Sound, Clip>> = vec![];
 
     for event in event_future {
         match event {
             Event::Voice(sound) => {
+                let (duration, gain_db) = sound
+                    .probe
+                    .await
+                    .unwrap()
+                    .with_context(|| "Audio Asset Generator")?;
+
                 events.push(Event::Voice(Sound {
-                    duration: sound
-                        .duration
-                        .await
-                        .unwrap()
-                        .with_context(|| "Audio Asset Generator")?,
+                    duration,
                     path: sound.path,
                     volume: sound.volume,
+                    text: sound.text,
+                    gain_db,
                 }));
             }
             Event::SoundEffect(sound) => {
+                let (duration, gain_db) = sound
+                    .probe
+                    .await
+                    .unwrap()
+                    .with_context(|| "Audio Asset Generator")?;
+
                 events.push(Event::SoundEffect(Sound {
-                    duration: sound
-                        .duration
-                        .await
-                        .unwrap()
-                        .with_context(|| "Audio Asset Generator")?,
+                    duration,
                     path: sound.path,
                     volume: sound.volume,
+                    text: sound.text,
+                    gain_db,
                 }));
             }
-            Event::MVBGMMarker { path, volume } => {
-                events.push(Event::MVBGMMarker {
-                    path: path.clone(),
-                    volume: volume.clone(),
-                });
+            Event::VPageMarker(clip) => {
+                let (duration, has_audio, gain_db) = clip
+                    .probe
+                    .await
+                    .unwrap()
+                    .with_context(|| "Video Asset Prober")?;
+
+                if duration <= 0.0 {
+                    anyhow::bail!(
+                        "Video clip is empty after trimming: {}",
+                        clip.path.display()
+                    );
+                }
+
+                events.push(Event::VPageMarker(Clip {
+                    duration,
+                    path: clip.path,
+                    volume: clip.volume,
+                    start: clip.start,
+                    has_audio,
+                    gain_db,
+                }));
+            }
+            Event::MVBGMMarker(bgm) => {
+                let gain_db = bgm_gains
+                    .pop_front()
+                    .unwrap()
+                    .await
+                    .unwrap()
+                    .with_context(|| "BGM Loudness Analyzer")?;
+
+                events.push(Event::MVBGMMarker(Bgm { gain_db, ..bgm }));
             }
             Event::BlankMs(duration) => {
                 events.push(Event::BlankMs(duration));
             }
-            Event::IPageMarker { path } => {
-                events.push(Event::IPageMarker { path });
+            Event::IPageMarker { path, ken_burns } => {
+                events.push(Event::IPageMarker { path, ken_burns });
+            }
+            Event::MPageMarker {
+                source,
+                marp_page_nth,
+                title,
+            } => {
+                events.push(Event::MPageMarker {
+                    source,
+                    marp_page_nth,
+                    title,
+                });
+            }
+            Event::ChapterMarker { title } => {
+                events.push(Event::ChapterMarker { title });
             }
-            Event::MPageMarker { marp_page_nth } => {
-                events.push(Event::MPageMarker { marp_page_nth });
+            Event::TransitionMarker { transition } => {
+                events.push(Event::TransitionMarker { transition });
             }
             Event::CPageMarker { color } => {
                 events.push(Event::CPageMarker { color });
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::config::Profile;
+use super::{Environment, ProfileOverrides};
+
+/// Containers `video_container` is expected to be (the extension of the output)
+const VIDEO_CONTAINERS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi"];
+
+/// A problem in the config, with the TOML key path of the value
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.key, self.message)
+    }
+}
+
+/// Find the command like the shell does; paths with a `/` are taken as they are
+fn find_bin(bin: &str) -> Option<PathBuf> {
+    if bin.contains('/') {
+        let path = PathBuf::from(bin);
+        return path.is_file().then_some(path);
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(bin))
+        .find(|path| path.is_file())
+}
+
+/// For reporting in the same order every time
+fn sorted<T>(map: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+impl Environment {
+    /// The config files loaded, in the order they are applied
+    pub fn config_paths(&self) -> &[PathBuf] {
+        &self.config_paths
+    }
+
+    /// Check the values `try_init` can't, e.g. commands which are not installed
+    pub fn check_config(&self) -> Vec<ConfigProblem> {
+        let mut problems = vec![];
+
+        let mut problem = |key: String, message: String| {
+            problems.push(ConfigProblem { key, message });
+        };
+
+        let dep = &self.config.dep;
+        let tts = sorted(&self.config.tts);
+
+        let bins = [
+            ("dep.ffmpeg.bin".to_string(), &dep.ffmpeg.bin),
+            ("dep.ffprobe.bin".to_string(), &dep.ffprobe.bin),
+            ("dep.marp.bin".to_string(), &dep.marp.bin),
+        ]
+        .into_iter()
+        .chain(
+            tts.iter()
+                .map(|(name, tts)| (format!("tts.{name}.bin"), &tts.bin)),
+        );
+
+        for (key, bin) in bins {
+            if find_bin(bin).is_none() {
+                let place = if bin.contains('/') { "" } else { " in PATH" };
+                problem(key, format!("`{bin}` is not found{place}"));
+            }
+        }
+
+        for (name, profile) in sorted(&self.config.profile) {
+            for (key, message) in self.check_profile(profile) {
+                problem(format!("profile.{name}.{key}"), message);
+            }
+        }
+
+        problems
+    }
+
+    /// Check the overrides of the document on the profile, as `marpVideo.*` of the frontmatter
+    pub fn check_overrides(&self, overrides: &ProfileOverrides) -> Vec<ConfigProblem> {
+        let mut env = self.clone();
+        env.override_profile(overrides);
+
+        // Keys in the frontmatter; the rest are reported for the profile by `check_config`
+        let frontmatter_key = |key: &str| match key {
+            "voice" if overrides.voice.is_some() => Some("voice"),
+            "width" if overrides.width.is_some() => Some("width"),
+            "height" if overrides.height.is_some() => Some("height"),
+            "framerate" if overrides.framerate.is_some() => Some("framerate"),
+            "video_container" if overrides.video_container.is_some() => Some("videoContainer"),
+            _ => None,
+        };
+
+        env.check_profile(&env.config.profile[&env.profile])
+            .into_iter()
+            .filter_map(|(key, message)| {
+                Some(ConfigProblem {
+                    key: format!("marpVideo.{}", frontmatter_key(key)?),
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    /// Problems of the profile values, with their keys in the profile
+    fn check_profile(&self, profile: &Profile) -> Vec<(&'static str, String)> {
+        let mut problems = vec![];
+
+        let mut problem = |key: &'static str, message: String| {
+            problems.push((key, message));
+        };
+
+        for (key, value) in [("width", profile.width), ("height", profile.height)] {
+            if value == 0 || value % 2 != 0 {
+                problem(
+                    key,
+                    format!("must be a positive even number (yuv420p), not {value}"),
+                );
+            }
+        }
+
+        if profile.framerate == 0 {
+            problem("framerate", "must be positive".to_string());
+        }
+
+        if !VIDEO_CONTAINERS.contains(&profile.video_container.as_str()) {
+            problem(
+                "video_container",
+                format!(
+                    "unknown container `{}`, expected one of: {}",
+                    profile.video_container,
+                    VIDEO_CONTAINERS.join(", ")
+                ),
+            );
+        }
+
+        if !self.tts_is_profile_exists(&profile.voice) {
+            problem(
+                "voice",
+                format!("voice profile `tts.{}` is not configured", profile.voice),
+            );
+        }
+
+        if let Some(font_file) = profile.caption.as_ref().and_then(|c| c.font_file.as_ref()) {
+            if !Path::new(font_file).is_file() {
+                problem(
+                    "caption.font_file",
+                    format!("{} is not found", font_file.display()),
+                );
+            }
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(problems: &[ConfigProblem]) -> Vec<&str> {
+        problems.iter().map(|p| p.key.as_str()).collect()
+    }
+
+    #[test]
+    fn overrides_are_checked_on_the_profile() {
+        let env = Environment::for_test(Path::new("/doc/main.md"));
+
+        let overrides = ProfileOverrides {
+            width: Some(1081),
+            height: Some(720),
+            video_container: Some("foo".to_string()),
+            voice: Some("narrator".to_string()),
+            ..ProfileOverrides::default()
+        };
+
+        assert_eq!(
+            keys(&env.check_overrides(&overrides)),
+            [
+                "marpVideo.width",
+                "marpVideo.videoContainer",
+                "marpVideo.voice"
+            ]
+        );
+
+        assert!(env.check_overrides(&ProfileOverrides::default()).is_empty());
+    }
+}
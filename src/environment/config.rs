@@ -1,9 +1,16 @@
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
+use anyhow::{bail, Context};
 use serde_env_field::EnvField;
 use serde::Deserialize;
 
+use crate::event::Transition;
+use crate::pronunciation::Dictionary;
+
+/// The first layer of the config
+const DEFAULT_CONFIG: &str = include_str!("default.toml");
+
 fn default_ffprobe_bin() -> String {
     String::from("ffprobe")
 }
@@ -20,6 +27,10 @@ fn default_tts_bin() -> String {
     String::from("marp-video-tts")
 }
 
+fn default_voice() -> String {
+    String::from("default")
+}
+
 fn default_video_container() -> String {
     String::from("mp4")
 }
@@ -32,6 +43,58 @@ fn default_height() -> usize {
     720
 }
 
+fn default_ducking_level() -> f32 {
+    30.0
+}
+
+fn default_ducking_attack_ms() -> usize {
+    200
+}
+
+fn default_ducking_release_ms() -> usize {
+    500
+}
+
+fn default_framerate() -> usize {
+    30
+}
+
+fn default_subtitle_enabled() -> bool {
+    true
+}
+
+fn default_subtitle_max_line_chars() -> usize {
+    42
+}
+
+fn default_subtitle_max_lines() -> usize {
+    2
+}
+
+fn default_subtitle_max_duration_ms() -> usize {
+    7000
+}
+
+fn default_caption_font_color() -> String {
+    String::from("white")
+}
+
+fn default_caption_box_color() -> String {
+    String::from("black@0.5")
+}
+
+fn default_caption_position() -> CaptionPosition {
+    CaptionPosition::Bottom
+}
+
+fn default_caption_margin() -> usize {
+    40
+}
+
+fn default_caption_max_line_chars() -> usize {
+    32
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Ffmpeg {
@@ -73,6 +136,13 @@ pub(super) struct Tts {
 
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
     pub envs: HashMap<String, String>,
+
+    /// Applied after the global pronunciation dictionary
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub pronunciation: BTreeMap<String, String>,
+
+    /// Template of `[em ...]` for TTS with `{}` for the text, e.g. `<emphasis>{}</emphasis>`
+    pub emphasis: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -84,6 +154,163 @@ pub(super) struct Dependencies {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Subtitle {
+    #[serde(default = "default_subtitle_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_subtitle_max_line_chars")]
+    pub max_line_chars: usize,
+
+    #[serde(default = "default_subtitle_max_lines")]
+    pub max_lines: usize,
+
+    #[serde(default = "default_subtitle_max_duration_ms")]
+    pub max_duration_ms: usize,
+}
+
+impl Default for Subtitle {
+    fn default() -> Self {
+        Self {
+            enabled: default_subtitle_enabled(),
+            max_line_chars: default_subtitle_max_line_chars(),
+            max_lines: default_subtitle_max_lines(),
+            max_duration_ms: default_subtitle_max_duration_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Caption {
+    /// fontconfig font name
+    pub font: Option<String>,
+
+    pub font_file: Option<PathBuf>,
+
+    /// Defaults to 1/15 of the video height
+    pub font_size: Option<usize>,
+
+    #[serde(default = "default_caption_font_color")]
+    pub font_color: String,
+
+    #[serde(default = "default_caption_box_color")]
+    pub box_color: String,
+
+    #[serde(default = "default_caption_position")]
+    pub position: CaptionPosition,
+
+    #[serde(default = "default_caption_margin")]
+    pub margin: usize,
+
+    #[serde(default = "default_caption_max_line_chars")]
+    pub max_line_chars: usize,
+
+    #[serde(default = "default_subtitle_max_lines")]
+    pub max_lines: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ducking {
+    /// BGM volume (%) while speaking
+    #[serde(default = "default_ducking_level")]
+    pub level: f32,
+
+    #[serde(default = "default_ducking_attack_ms")]
+    pub attack_ms: usize,
+
+    #[serde(default = "default_ducking_release_ms")]
+    pub release_ms: usize,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            level: default_ducking_level(),
+            attack_ms: default_ducking_attack_ms(),
+            release_ms: default_ducking_release_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct BgmDefaults {
+    #[serde(default)]
+    pub fade_in_ms: usize,
+
+    #[serde(default)]
+    pub fade_out_ms: usize,
+
+    #[serde(default)]
+    pub crossfade_ms: usize,
+}
+
+/// EBU R128 integrated loudness targets (LUFS) for each channel; None: as is
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Loudness {
+    pub voice: Option<f64>,
+    pub sound_effect: Option<f64>,
+    pub bgm: Option<f64>,
+}
+
+fn default_mixer_limiter() -> bool {
+    true
+}
+
+fn default_mixer_limit_db() -> f64 {
+    -1.0
+}
+
+/// Final mix of the foreground (voice, SE, clips) and background (BGM) channels
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Mixer {
+    #[serde(default)]
+    pub fg_gain_db: f64,
+
+    #[serde(default)]
+    pub bg_gain_db: f64,
+
+    /// amix's input normalization (divides each input by the number of inputs)
+    #[serde(default)]
+    pub normalize: bool,
+
+    #[serde(default = "default_mixer_limiter")]
+    pub limiter: bool,
+
+    #[serde(default = "default_mixer_limit_db")]
+    pub limit_db: f64,
+
+    #[serde(default)]
+    pub dropout_transition_ms: usize,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self {
+            fg_gain_db: 0.0,
+            bg_gain_db: 0.0,
+            normalize: false,
+            limiter: default_mixer_limiter(),
+            limit_db: default_mixer_limit_db(),
+            dropout_transition_ms: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(super) struct Profile {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub ffmpeg_args: Vec<String>,
@@ -94,11 +321,39 @@ pub(super) struct Profile {
     #[serde(default = "default_video_container")]
     pub video_container: String,
 
+    /// Voice profile of `speak` and presenter notes
+    #[serde(default = "default_voice")]
+    pub voice: String,
+
     #[serde(default = "default_width")]
     pub width: usize,
 
     #[serde(default = "default_height")]
     pub height: usize,
+
+    #[serde(default = "default_framerate")]
+    pub framerate: usize,
+
+    /// Default transition between pages (hard cut if not set)
+    pub transition: Option<Transition>,
+
+    #[serde(default)]
+    pub subtitle: Subtitle,
+
+    /// Burn-in captions (disabled if not set)
+    pub caption: Option<Caption>,
+
+    /// Duck BGM under narration (disabled if not set, unless `bgm` sets `duck=`)
+    pub ducking: Option<Ducking>,
+
+    #[serde(default)]
+    pub bgm: BgmDefaults,
+
+    #[serde(default)]
+    pub loudness: Loudness,
+
+    #[serde(default)]
+    pub mixer: Mixer,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -108,11 +363,239 @@ pub(super) struct Config {
     pub dep: Dependencies,
     pub tts: HashMap<String, Tts>,
     pub profile: HashMap<String, Profile>,
+
+    #[serde(default)]
+    pub pronunciation: BTreeMap<String, String>,
+}
+
+/// Merge `layer` into `base`; tables are merged recursively, other values are replaced
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolve `extends = "<name>"` of the tables in `section` (`profile` or `tts`)
+fn resolve_extends(config: &mut toml::Table, section: &str) -> anyhow::Result<()> {
+    let Some(toml::Value::Table(tables)) = config.get_mut(section) else {
+        return Ok(());
+    };
+
+    let mut resolved = toml::Table::new();
+
+    for name in tables.keys() {
+        resolve(tables, section, name, &mut resolved, &mut vec![])?;
+    }
+
+    *tables = resolved;
+
+    Ok(())
+}
+
+/// `chain` is the names extending `name`, for detecting cycles
+fn resolve(
+    tables: &toml::Table,
+    section: &str,
+    name: &str,
+    resolved: &mut toml::Table,
+    chain: &mut Vec<String>,
+) -> anyhow::Result<toml::Value> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+
+    if let Some(start) = chain.iter().position(|n| n == name) {
+        let cycle: Vec<&str> = chain[start..]
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .collect();
+
+        bail!("`{section}.{name}` extends itself: {}", cycle.join(" -> "));
+    }
+
+    // Anything else than a table is left to the deserializer to report
+    let Some(toml::Value::Table(table)) = tables.get(name) else {
+        return Ok(tables[name].clone());
+    };
+
+    let mut table = table.clone();
+
+    let value = match table.remove("extends") {
+        None => toml::Value::Table(table),
+        Some(toml::Value::String(parent)) => {
+            if !tables.contains_key(&parent) {
+                bail!("`{section}.{name}` extends `{parent}`, which is not configured");
+            }
+
+            chain.push(name.to_string());
+            let base = resolve(tables, section, &parent, resolved, chain)?;
+            chain.pop();
+
+            let toml::Value::Table(mut base) = base else {
+                bail!("`{section}.{name}` extends `{parent}`, which is not a table");
+            };
+
+            merge(&mut base, table);
+            toml::Value::Table(base)
+        }
+        Some(value) => {
+            bail!(
+                "`{section}.{name}.extends` must be a name, not {}",
+                value.type_str()
+            );
+        }
+    };
+
+    resolved.insert(name.to_string(), value.clone());
+
+    Ok(value)
 }
 
 impl Config {
-    pub fn try_init(path: &Path) -> anyhow::Result<Self> {
-        let cfg = std::fs::read_to_string(&path)?;
-        Ok(toml::from_str(&cfg)?)
+    /// Load the config files on top of the built-in defaults, later ones overriding earlier ones
+    pub fn try_init(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut layers = vec![];
+
+        for path in paths {
+            let cfg = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+
+            let layer: toml::Table = toml::from_str(&cfg)
+                .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+            layers.push(layer);
+        }
+
+        Self::try_from_layers(layers)
+    }
+
+    /// The config of the layers on top of the built-in defaults
+    pub(super) fn try_from_layers(layers: Vec<toml::Table>) -> anyhow::Result<Self> {
+        let mut table: toml::Table = toml::from_str(DEFAULT_CONFIG)?;
+
+        for layer in layers {
+            merge(&mut table, layer);
+        }
+
+        resolve_extends(&mut table, "profile")?;
+        resolve_extends(&mut table, "tts")?;
+
+        let config: Self = table.try_into()?;
+
+        let dictionaries = std::iter::once(&config.pronunciation)
+            .chain(config.tts.values().map(|tts| &tts.pronunciation));
+
+        for dictionary in dictionaries {
+            if let Some(e) = Dictionary::default().merge(dictionary).into_iter().next() {
+                return Err(e.into());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_toml(s: &str, section: &str) -> anyhow::Result<toml::Table> {
+        let mut table: toml::Table = toml::from_str(s)?;
+        resolve_extends(&mut table, section)?;
+        Ok(table)
+    }
+
+    fn layers(layers: &[&str]) -> Config {
+        let layers = layers.iter().map(|s| toml::from_str(s).unwrap()).collect();
+        Config::try_from_layers(layers).unwrap()
+    }
+
+    #[test]
+    fn layers_override_key_by_key() {
+        let config = layers(&[
+            "[profile.default]\nwidth = 1280\nheight = 720\n[profile.default.mixer]\nlimit_db = -2.0\n",
+            "[profile.default]\nheight = 1080\n[profile.default.mixer]\nfg_gain_db = 3.0\n",
+        ]);
+
+        let profile = &config.profile["default"];
+
+        assert_eq!((profile.width, profile.height), (1280, 1080));
+        assert_eq!(profile.mixer.limit_db, -2.0);
+        assert_eq!(profile.mixer.fg_gain_db, 3.0);
+
+        // The built-in defaults stay under the layers
+        assert!(config.tts.contains_key("default"));
+        assert_eq!(config.dep.ffmpeg.bin, "ffmpeg");
+    }
+
+    #[test]
+    fn layers_replace_arrays() {
+        let config = layers(&[
+            "[profile.default]\nffmpeg_args = [\"-a\", \"-b\"]\n",
+            "[profile.default]\nffmpeg_args = [\"-c\"]\n",
+        ]);
+
+        assert_eq!(config.profile["default"].ffmpeg_args, ["-c"]);
+    }
+
+    #[test]
+    fn extends_merges_tables() {
+        let table = resolve_toml(
+            "[tts.narrator]\nargs = [\"-v\"]\nenvs = { SPEED = \"1.0\", PITCH = \"2\" }\n\
+             [tts.fast]\nextends = \"narrator\"\nenvs.SPEED = \"1.5\"\n\
+             [tts.faster]\nextends = \"fast\"\nargs = []\n",
+            "tts",
+        )
+        .unwrap();
+
+        let expected: toml::Table = toml::from_str(
+            "[tts.narrator]\nargs = [\"-v\"]\nenvs = { SPEED = \"1.0\", PITCH = \"2\" }\n\
+             [tts.fast]\nargs = [\"-v\"]\nenvs = { SPEED = \"1.5\", PITCH = \"2\" }\n\
+             [tts.faster]\nargs = []\nenvs = { SPEED = \"1.5\", PITCH = \"2\" }\n",
+        )
+        .unwrap();
+
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let e = resolve_toml(
+            "[profile.a]\nextends = \"b\"\n[profile.b]\nextends = \"c\"\n[profile.c]\nextends = \"b\"\n",
+            "profile",
+        )
+        .unwrap_err();
+
+        assert_eq!(e.to_string(), "`profile.b` extends itself: b -> c -> b");
+    }
+
+    #[test]
+    fn extends_self() {
+        let e = resolve_toml("[profile.a]\nextends = \"a\"\n", "profile").unwrap_err();
+
+        assert_eq!(e.to_string(), "`profile.a` extends itself: a -> a");
+    }
+
+    #[test]
+    fn extends_unknown() {
+        let e = resolve_toml("[profile.a]\nextends = \"nope\"\n", "profile").unwrap_err();
+
+        assert_eq!(
+            e.to_string(),
+            "`profile.a` extends `nope`, which is not configured"
+        );
+    }
+
+    #[test]
+    fn profile_rejects_unknown_keys() {
+        let e = toml::from_str::<Profile>("widht = 3").unwrap_err();
+
+        assert!(e.to_string().contains("unknown field `widht`"), "{e}");
     }
 }
@@ -4,38 +4,149 @@ use std::collections::HashMap;
 use anyhow::Context;
 use directories::ProjectDirs;
 
+use crate::event::Transition;
+use crate::pronunciation::Dictionary;
+
+mod check;
+
 mod config;
 use config::Config;
+pub use config::{Caption, CaptionPosition, Ducking};
+
+const CONFIG_FILE_NAME: &str = "marp-video.toml";
+
+/// Profile values a document overrides (`marpVideo` in the frontmatter)
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOverrides {
+    pub voice: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub framerate: Option<usize>,
+    pub video_container: Option<String>,
+    pub ffmpeg_args: Option<Vec<String>>,
+    pub marp_args: Option<Vec<String>>,
+    pub bgm_fade_in_ms: Option<usize>,
+    pub bgm_fade_out_ms: Option<usize>,
+    pub bgm_crossfade_ms: Option<usize>,
+}
+
+/// Config files in the order they are applied (the built-in defaults come first)
+///
+/// 1. `marp-video.toml` in the user config dir (e.g. `$XDG_CONFIG_HOME/marp-video`)
+/// 2. The nearest `marp-video.toml` walking up from the directory of the document
+/// 3. `--config`
+fn config_paths(md_path: &Path, explicit: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![];
+
+    if let Some(dirs) = ProjectDirs::from("", "", env!("CARGO_PKG_NAME")) {
+        let path = dirs.config_dir().join(CONFIG_FILE_NAME);
+
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+
+    let project = md_path
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file());
+
+    if let Some(path) = project {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    // Must exist, unlike the others
+    paths.extend(explicit.map(Path::to_path_buf));
+
+    paths
+}
 
 #[derive(Debug, Clone)]
 pub struct Environment {
     config: Config,
+    config_paths: Vec<PathBuf>,
     abs_md_path: PathBuf,
     profile: String,
 }
 
 impl Environment {
-    pub fn try_init(md_path: &Path, profile: &str) -> anyhow::Result<Self> {
-        let dirs = ProjectDirs::from("", "", env!("CARGO_PKG_NAME")).with_context(|| {
-            "Failed to get application base config directory. Do you have home directory?"
-        })?;
-
-        let config_path = dirs.config_dir().join("marp-video.toml");
-        let config_path_str = config_path.to_string_lossy();
+    /// `config` is the path given by `--config`, which overrides the other config files
+    pub fn try_init(md_path: &Path, profile: &str, config: Option<&Path>) -> anyhow::Result<Self> {
+        let md_path = md_path
+            .canonicalize()
+            .with_context(|| format!("Failed to find {}", md_path.display()))?;
 
-        let config = Config::try_init(&config_path)
-            .with_context(|| format!("Failed to read config file: {config_path_str}"))?;
+        let config_paths = config_paths(&md_path, config);
 
-        let md_path = md_path.canonicalize()?;
+        let config = Config::try_init(&config_paths).with_context(|| "Failed to load config")?;
 
-        config
-            .profile
-            .get(profile)
-            .ok_or(anyhow::anyhow!("The specified profile ({profile}) is not configured"))?;
+        config.profile.get(profile).ok_or(anyhow::anyhow!(
+            "The specified profile ({profile}) is not configured"
+        ))?;
 
         let profile = profile.to_string();
 
-        Ok(Self { config, abs_md_path: md_path, profile })
+        Ok(Self {
+            config,
+            config_paths,
+            abs_md_path: md_path,
+            profile,
+        })
+    }
+
+    /// The built-in defaults only, for a document which may not exist
+    #[cfg(test)]
+    pub fn for_test(md_path: &Path) -> Self {
+        Self::for_test_config(md_path, "")
+    }
+
+    /// `config` on top of the built-in defaults
+    #[cfg(test)]
+    pub fn for_test_config(md_path: &Path, config: &str) -> Self {
+        Self {
+            config: Config::try_from_layers(vec![toml::from_str(config).unwrap()]).unwrap(),
+            config_paths: vec![],
+            abs_md_path: md_path.to_path_buf(),
+            profile: "default".to_string(),
+        }
+    }
+
+    /// Apply the overrides of the document to the profile
+    pub fn override_profile(&mut self, overrides: &ProfileOverrides) {
+        let profile = self.config.profile.get_mut(&self.profile).unwrap();
+
+        let ProfileOverrides {
+            voice,
+            width,
+            height,
+            framerate,
+            video_container,
+            ffmpeg_args,
+            marp_args,
+            bgm_fade_in_ms,
+            bgm_fade_out_ms,
+            bgm_crossfade_ms,
+        } = overrides.clone();
+
+        profile.voice = voice.unwrap_or(std::mem::take(&mut profile.voice));
+        profile.width = width.unwrap_or(profile.width);
+        profile.height = height.unwrap_or(profile.height);
+        profile.framerate = framerate.unwrap_or(profile.framerate);
+        profile.video_container =
+            video_container.unwrap_or(std::mem::take(&mut profile.video_container));
+        profile.ffmpeg_args = ffmpeg_args.unwrap_or(std::mem::take(&mut profile.ffmpeg_args));
+        profile.marp_args = marp_args.unwrap_or(std::mem::take(&mut profile.marp_args));
+        profile.bgm.fade_in_ms = bgm_fade_in_ms.unwrap_or(profile.bgm.fade_in_ms);
+        profile.bgm.fade_out_ms = bgm_fade_out_ms.unwrap_or(profile.bgm.fade_out_ms);
+        profile.bgm.crossfade_ms = bgm_crossfade_ms.unwrap_or(profile.bgm.crossfade_ms);
+    }
+
+    /// Voice profile of `speak` and presenter notes
+    pub fn default_voice_profile(&self) -> &str {
+        &self.config.profile[&self.profile].voice
     }
 
     pub fn video_width(&self) -> usize {
@@ -46,6 +157,14 @@ impl Environment {
         self.config.profile[&self.profile].height
     }
 
+    pub fn video_framerate(&self) -> usize {
+        self.config.profile[&self.profile].framerate
+    }
+
+    pub fn default_transition(&self) -> Option<&Transition> {
+        self.config.profile[&self.profile].transition.as_ref()
+    }
+
     pub fn marp_additional_args(&self) -> Vec<String> {
         let mut args = vec![];
         args.extend_from_slice(&self.config.dep.marp.global_args);
@@ -64,6 +183,79 @@ impl Environment {
         &self.config.profile[&self.profile].video_container
     }
 
+    pub fn subtitle_enabled(&self) -> bool {
+        self.config.profile[&self.profile].subtitle.enabled
+    }
+
+    pub fn subtitle_max_line_chars(&self) -> usize {
+        self.config.profile[&self.profile].subtitle.max_line_chars
+    }
+
+    pub fn subtitle_max_lines(&self) -> usize {
+        self.config.profile[&self.profile].subtitle.max_lines
+    }
+
+    pub fn subtitle_max_duration(&self) -> f64 {
+        self.config.profile[&self.profile].subtitle.max_duration_ms as f64 / 1000.0
+    }
+
+    pub fn caption(&self) -> Option<&Caption> {
+        self.config.profile[&self.profile].caption.as_ref()
+    }
+
+    pub fn ducking(&self) -> Option<&Ducking> {
+        self.config.profile[&self.profile].ducking.as_ref()
+    }
+
+    pub fn bgm_fade_in_ms(&self) -> usize {
+        self.config.profile[&self.profile].bgm.fade_in_ms
+    }
+
+    pub fn bgm_fade_out_ms(&self) -> usize {
+        self.config.profile[&self.profile].bgm.fade_out_ms
+    }
+
+    pub fn bgm_crossfade_ms(&self) -> usize {
+        self.config.profile[&self.profile].bgm.crossfade_ms
+    }
+
+    pub fn loudness_voice_target(&self) -> Option<f64> {
+        self.config.profile[&self.profile].loudness.voice
+    }
+
+    pub fn loudness_sound_effect_target(&self) -> Option<f64> {
+        self.config.profile[&self.profile].loudness.sound_effect
+    }
+
+    pub fn loudness_bgm_target(&self) -> Option<f64> {
+        self.config.profile[&self.profile].loudness.bgm
+    }
+
+    pub fn mixer_fg_gain_db(&self) -> f64 {
+        self.config.profile[&self.profile].mixer.fg_gain_db
+    }
+
+    pub fn mixer_bg_gain_db(&self) -> f64 {
+        self.config.profile[&self.profile].mixer.bg_gain_db
+    }
+
+    pub fn mixer_normalize(&self) -> bool {
+        self.config.profile[&self.profile].mixer.normalize
+    }
+
+    /// Limiter ceiling in dBFS (None: disabled)
+    pub fn mixer_limit_db(&self) -> Option<f64> {
+        let mixer = &self.config.profile[&self.profile].mixer;
+        mixer.limiter.then_some(mixer.limit_db)
+    }
+
+    pub fn mixer_dropout_transition(&self) -> f64 {
+        self.config.profile[&self.profile]
+            .mixer
+            .dropout_transition_ms as f64
+            / 1000.0
+    }
+
     pub fn ffmpeg_bin(&self) -> &str {
         &self.config.dep.ffmpeg.bin
     }
@@ -77,13 +269,32 @@ impl Environment {
     }
 
     pub fn tts_is_profile_exists(&self, voice_profile: &str) -> bool {
-        self.config.tts.get(voice_profile).is_some()
+        self.config.tts.contains_key(voice_profile)
     }
 
     pub fn tts_bin(&self, voice_profile: &str) -> &str {
         &self.config.tts[voice_profile].bin
     }
 
+    /// Template of `[em ...]`; None: the text is spoken as it is
+    pub fn tts_emphasis(&self, voice_profile: &str) -> Option<&str> {
+        self.config.tts.get(voice_profile)?.emphasis.as_deref()
+    }
+
+    /// The global dictionary with the voice profile's one on top
+    pub fn pronunciation(&self, voice_profile: &str) -> Dictionary {
+        let mut dictionary = Dictionary::default();
+
+        // Both are validated on loading the config
+        dictionary.merge(&self.config.pronunciation);
+
+        if let Some(tts) = self.config.tts.get(voice_profile) {
+            dictionary.merge(&tts.pronunciation);
+        }
+
+        dictionary
+    }
+
     pub fn marp_envs(&self) -> &HashMap<String, String> {
         &self.config.dep.marp.envs
     }
@@ -120,7 +331,7 @@ impl Environment {
     }
 
     pub fn init_voice_cache_dir(&self, voice_profile: &str) -> anyhow::Result<()> {
-        std::fs::create_dir_all(&self.voice_cache_dir(voice_profile))?;
+        std::fs::create_dir_all(self.voice_cache_dir(voice_profile))?;
         Ok(())
     }
 
@@ -129,6 +340,30 @@ impl Environment {
             .join(format!("{:x}.bin", md5::compute(text)))
     }
 
+    pub fn loudness_cache_dir(&self) -> PathBuf {
+        self.cache_root_dir().join("loudness")
+    }
+
+    pub fn init_loudness_cache_dir(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(self.loudness_cache_dir())?;
+        Ok(())
+    }
+
+    /// Keyed by path, size and mtime, so an updated file is measured again
+    pub fn loudness_cache(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos();
+
+        let key = format!("{}:{}:{mtime}", path.display(), metadata.len());
+
+        Ok(self
+            .loudness_cache_dir()
+            .join(format!("{:x}.lufs", md5::compute(key))))
+    }
+
     pub fn project_root_dir(&self) -> PathBuf {
         self.cache_root_dir().join(format!(
             "work_{:x}",
@@ -137,7 +372,58 @@ impl Environment {
     }
 
     pub fn init_project_root_dir(&self) -> anyhow::Result<()> {
-        std::fs::create_dir_all(&self.project_root_dir())?;
+        std::fs::create_dir_all(self.project_root_dir())?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn config_paths_nearest_project_then_explicit() {
+        let dir = TempDir::new("config-paths");
+
+        let root = dir.write(CONFIG_FILE_NAME, "");
+        let md_path = dir.write("a/b/main.md", "");
+        let explicit = dir.path().join("explicit.toml");
+
+        let paths = config_paths(&md_path, Some(&explicit));
+
+        // The user config (if any) comes first
+        assert!(paths.ends_with(&[root, explicit.clone()]), "{paths:?}");
+
+        let nearer = dir.write(&format!("a/{CONFIG_FILE_NAME}"), "");
+        let paths = config_paths(&md_path, Some(&explicit));
+
+        assert!(paths.ends_with(&[nearer, explicit]), "{paths:?}");
+    }
+
+    #[test]
+    fn override_profile_keeps_the_values_not_overridden() {
+        let mut env = Environment::for_test_config(
+            Path::new("/doc/main.md"),
+            "[profile.default]\nvoice = \"narrator\"\nwidth = 1920\nheight = 1080\n\
+             framerate = 30\nmarp_args = [\"--html\"]\nbgm = { fade_in_ms = 100, fade_out_ms = 200 }\n",
+        );
+
+        env.override_profile(&ProfileOverrides {
+            voice: Some("fast".to_string()),
+            width: Some(1280),
+            framerate: Some(60),
+            marp_args: Some(vec![]),
+            bgm_fade_out_ms: Some(500),
+            ..ProfileOverrides::default()
+        });
+
+        assert_eq!(env.default_voice_profile(), "fast");
+        assert_eq!(
+            (env.video_width(), env.video_height(), env.video_framerate()),
+            (1280, 1080, 60)
+        );
+        assert_eq!(env.marp_additional_args(), Vec::<String>::new());
+        assert_eq!((env.bgm_fade_in_ms(), env.bgm_fade_out_ms()), (100, 500));
+    }
+}
@@ -1,7 +1,65 @@
 use std::path::PathBuf;
 
+use serde::Deserialize;
+
+/// ffmpeg xfade transition between pages
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transition {
+    /// xfade transition name (fade, wipeleft, slideup, ...)
+    pub effect: String,
+
+    #[serde(default = "Transition::default_duration_ms")]
+    pub duration_ms: usize,
+}
+
+impl Transition {
+    pub fn default_duration_ms() -> usize {
+        500
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pan {
+    Center,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Pan/zoom over an image page
+#[derive(Debug, Clone, Copy)]
+pub struct KenBurns {
+    pub zoom_from: f64,
+    pub zoom_to: f64,
+    pub pan: Pan,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bgm {
+    /// None: silence
+    pub path: Option<PathBuf>,
+    pub volume: f32,
+
+    /// BGM volume (%) while speaking; falls back to the profile's ducking level
+    pub duck: Option<f32>,
+
+    /// Falls back to the profile's bgm defaults
+    pub fade_in_ms: Option<usize>,
+
+    /// Falls back to the profile's bgm defaults
+    pub fade_out_ms: Option<usize>,
+
+    /// Crossfade from the previous BGM; falls back to the profile's bgm defaults
+    pub crossfade_ms: Option<usize>,
+
+    /// Loudness normalization gain (filled in by asset_preparator)
+    pub gain_db: f64,
+}
+
 #[derive(Debug, Clone)]
-pub enum Event<Voice, FgSound> {
+pub enum Event<Voice, FgSound, Video> {
     Voice(Voice),
 
     SoundEffect(FgSound),
@@ -9,14 +67,12 @@ pub enum Event<Voice, FgSound> {
     BlankMs(usize),
 
     /// Marp Video BGM Marker
-    MVBGMMarker {
-        path: Option<PathBuf>,
-        volume: f32,
-    },
+    MVBGMMarker(Bgm),
 
     /// Marp Video Virtual Page (Image)
     IPageMarker {
         path: PathBuf,
+        ken_burns: Option<KenBurns>,
     },
 
     /// Marp Video Virtual Page (Color)
@@ -24,24 +80,42 @@ pub enum Event<Voice, FgSound> {
         color: String,
     },
 
+    /// Marp Video Virtual Page (Video Clip)
+    VPageMarker(Video),
+
     /// Marp Page
     MPageMarker {
+        /// Index of the Marp document (`DocEvents::sources`)
+        source: usize,
         marp_page_nth: usize,
+
+        /// First heading in the page
+        title: Option<String>,
+    },
+
+    /// Marp Video Chapter Title (overrides the page title)
+    ChapterMarker {
+        title: String,
+    },
+
+    /// Marp Video Transition to the next page (None: hard cut)
+    TransitionMarker {
+        transition: Option<Transition>,
     },
 }
 
-impl<Voice, FgSound> Event<Voice, FgSound> {
+impl<Voice, FgSound, Video> Event<Voice, FgSound, Video> {
     pub fn is_page(&self) -> bool {
-        match self {
-            Self::IPageMarker { .. } | Self::CPageMarker { .. } | Self::MPageMarker { .. } => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Self::IPageMarker { .. }
+                | Self::CPageMarker { .. }
+                | Self::MPageMarker { .. }
+                | Self::VPageMarker(_)
+        )
     }
 
     pub fn is_bgm_event(&self) -> bool {
-        match self {
-            Self::MVBGMMarker { .. } => true,
-            _ => false,
-        }
+        matches!(self, Self::MVBGMMarker(_))
     }
 }
@@ -0,0 +1,700 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::event::{Bgm, Event, KenBurns, Pan, Transition};
+
+use super::sentence;
+use super::tokenizer::{self, Token, Tokenizer};
+use super::{RawFgSoundEvent, RawVideoEvent, RawVoiceEvent};
+
+const DIRECTIVES: &[&str] = &[
+    "speak",
+    "speak_in",
+    "blank",
+    "chapter",
+    "transition",
+    "bgcolor",
+    "bgimage",
+    "sound_effect",
+    "video",
+    "bgm",
+    "include",
+];
+
+#[derive(Debug, Clone)]
+pub struct DirectiveError {
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl DirectiveError {
+    pub(super) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub(super) fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Relative to the directory of the document
+fn resource_path(dir: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.has_root() {
+        path
+    } else {
+        dir.join(path)
+    }
+}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, DirectiveError> {
+    value.parse().map_err(|_| {
+        DirectiveError::new(format!("`{key}` is not a number: `{value}`"))
+            .with_hint("expected a number")
+    })
+}
+
+fn required<T>(directive: &str, key: &str, value: Option<T>) -> Result<T, DirectiveError> {
+    value.ok_or_else(|| {
+        DirectiveError::new(format!("`{key}` is required for `{directive}`"))
+            .with_hint(format!("{directive}: {key}=..."))
+    })
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = (prev + usize::from(ca != *cb)).min(row[j] + 1).min(cur + 1);
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// `did you mean ...?` for a misspelled key, or the list of the candidates
+pub(super) fn suggest(key: &str, candidates: &[&str]) -> String {
+    match candidates
+        .iter()
+        .map(|c| (edit_distance(key, c), c))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+    {
+        Some((_, c)) => format!("did you mean `{c}`?"),
+        None => format!("expected one of: {}", candidates.join(", ")),
+    }
+}
+
+/// A quoted string, or bare text in which `=` is taken literally (`path=a=b.mp3`)
+fn value_of(tokens: &[Token]) -> Option<String> {
+    match tokens {
+        [] => None,
+        [Token::Quoted(value)] => Some(value.clone()),
+        _ if tokens.iter().all(|t| !matches!(t, Token::Quoted(_))) => {
+            Some(tokens.iter().map(Token::to_string).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Split `key=value,key=value` into pairs; values may be quoted (`path="a,b.mp3"`).
+///
+/// If `positional` is set, a leading bare value (e.g. `bgimage: image.png`) is taken as that key.
+fn properties(
+    directive: &str,
+    s: &str,
+    positional: Option<&'static str>,
+    known: &[&'static str],
+) -> Result<Vec<(&'static str, String)>, DirectiveError> {
+    let tokens = Tokenizer::new(s, &[',', '=']).collect::<Result<Vec<_>, _>>()?;
+
+    let mut pairs = vec![];
+
+    for (n, property) in tokens.split(|t| *t == Token::Delimiter(',')).enumerate() {
+        match (property, positional) {
+            ([Token::Bare(key), Token::Delimiter('='), value @ ..], _)
+                if value_of(value).is_some() =>
+            {
+                let Some(key) = known.iter().find(|k| *k == key) else {
+                    return Err(DirectiveError::new(format!(
+                        "unknown property `{key}` for `{directive}`"
+                    ))
+                    .with_hint(suggest(key, known)));
+                };
+
+                pairs.push((*key, value_of(value).unwrap()));
+            }
+            ([Token::Bare(value) | Token::Quoted(value)], Some(key)) if n == 0 => {
+                pairs.push((key, value.clone()));
+            }
+            _ => {
+                let property: String = property.iter().map(Token::to_string).collect();
+
+                return Err(DirectiveError::new(format!(
+                    "malformed property `{property}` for `{directive}`"
+                ))
+                .with_hint("expected `key=value`"));
+            }
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// Split `speak: <text>` / `speak_in: <voice profile>: <text>` into the profile and the text
+/// `speak` uses the voice profile of the document
+fn voice<'a>(key: &str, val: &'a str, default: &str) -> Result<(String, &'a str), DirectiveError> {
+    if key == "speak" {
+        return Ok((default.to_string(), val));
+    }
+
+    let mut tokens = Tokenizer::new(val, &[':']);
+
+    let (Some(Token::Bare(profile) | Token::Quoted(profile)), Some(Token::Delimiter(_))) =
+        (tokens.next().transpose()?, tokens.next().transpose()?)
+    else {
+        return Err(DirectiveError::new("`speak_in` has no voice profile")
+            .with_hint("speak_in: <voice profile>: <text>"));
+    };
+
+    Ok((profile, tokens.rest()))
+}
+
+/// `|` keeps line breaks, `>` folds lines into one, `split` speaks each sentence separately
+struct Block {
+    fold: bool,
+    split: bool,
+}
+
+impl Block {
+    fn try_from_str(s: &str) -> Option<Self> {
+        let (fold, rest) = match s.trim().split_at_checked(1)? {
+            ("|", rest) => (false, rest.trim()),
+            (">", rest) => (true, rest.trim()),
+            _ => return None,
+        };
+
+        match rest {
+            "" => Some(Self { fold, split: false }),
+            "split" => Some(Self { fold, split: true }),
+            _ => None,
+        }
+    }
+
+    /// The (already dedented) lines into the texts to speak
+    fn texts(&self, lines: &[&str]) -> Vec<String> {
+        let text = if self.fold {
+            // Like YAML, blank lines become line breaks
+            lines
+                .split(|line| line.trim().is_empty())
+                .map(|paragraph| {
+                    paragraph
+                        .iter()
+                        .map(|l| l.trim())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .filter(|paragraph| !paragraph.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            lines.join("\n").trim().to_string()
+        };
+
+        if self.split {
+            sentence::split(&text)
+        } else if text.is_empty() {
+            vec![]
+        } else {
+            vec![text]
+        }
+    }
+}
+
+/// ASCII spaces and tabs only, so that slicing the line at it is always valid
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+/// A line (or a block) of `<!--mv ... -->`
+#[derive(Debug, Clone)]
+pub(super) enum Directive {
+    Event(Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>),
+
+    /// `include: path=chapter2.md`
+    Include(PathBuf),
+}
+
+impl Directive {
+    /// Parse the lines of `<!--mv ... -->`, with the index of the (first) line of each directive
+    ///
+    /// `speak: |` and `speak_in: <voice profile>: |` take the following more indented lines as the text.
+    pub(super) fn try_from_lines(
+        dir: &Path,
+        default_voice: &str,
+        lines: &[&str],
+    ) -> Vec<(usize, Result<Self, DirectiveError>)> {
+        let mut events = vec![];
+        let mut n = 0;
+
+        while n < lines.len() {
+            let line = lines[n];
+            let start = n;
+            n += 1;
+
+            let block = line
+                .split_once(':')
+                .map(|(key, val)| (key.trim(), val))
+                .filter(|(key, _)| matches!(*key, "speak" | "speak_in"))
+                .and_then(|(key, val)| {
+                    let (profile, rest) = voice(key, val, default_voice).ok()?;
+                    Some((key, profile, Block::try_from_str(rest)?))
+                });
+
+            let Some((key, profile, block)) = block else {
+                match Self::try_from_str(dir, default_voice, line) {
+                    Ok(Some(event)) => events.push((start, Ok(event))),
+                    Ok(None) => {}
+                    Err(e) => events.push((start, Err(e))),
+                }
+                continue;
+            };
+
+            let body_start = n;
+
+            while n < lines.len() && (lines[n].trim().is_empty() || indent(lines[n]) > indent(line))
+            {
+                n += 1;
+            }
+
+            let body = &lines[body_start..n];
+            let dedent = body
+                .iter()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| indent(l))
+                .min()
+                .unwrap_or(0);
+            let body: Vec<&str> = body.iter().map(|l| l.get(dedent..).unwrap_or("")).collect();
+
+            let texts = block.texts(&body);
+
+            if texts.is_empty() {
+                events.push((
+                    start,
+                    Err(DirectiveError::new(format!("`{key}` block has no text"))
+                        .with_hint("indent the lines of the text")),
+                ));
+            }
+
+            events.extend(texts.into_iter().map(|text| {
+                (
+                    start,
+                    Ok(Directive::Event(Event::Voice(RawVoiceEvent {
+                        text,
+                        profile: profile.clone(),
+                        speech: None,
+                    }))),
+                )
+            }));
+        }
+
+        events
+    }
+
+    /// Parse a line of `<!--mv ... -->`; blank lines are `Ok(None)`
+    fn try_from_str(
+        dir: &Path,
+        default_voice: &str,
+        s: &str,
+    ) -> Result<Option<Self>, DirectiveError> {
+        if s.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let Some((key, val)) = s.split_once(':').map(|(key, val)| (key.trim(), val.trim())) else {
+            return Err(
+                DirectiveError::new(format!("not a directive: `{}`", s.trim()))
+                    .with_hint("expected `key: value`"),
+            );
+        };
+
+        let event = match (key, val) {
+            ("speak" | "speak_in", val) => {
+                let (profile, text) = voice(key, val, default_voice)?;
+                let text = tokenizer::text(text);
+
+                if text.is_empty() {
+                    return Err(DirectiveError::new(format!("`{key}` has no text")));
+                }
+
+                Event::Voice(RawVoiceEvent {
+                    text,
+                    profile,
+                    speech: None,
+                })
+            }
+            ("blank", text) => Event::BlankMs(number("blank", text)?),
+            ("chapter", title) => Event::ChapterMarker {
+                title: tokenizer::text(title),
+            },
+            ("transition", properties) => {
+                let mut effect = None;
+                let mut duration_ms = Transition::default_duration_ms();
+
+                for (key, value) in self::properties(
+                    "transition",
+                    properties,
+                    Some("effect"),
+                    &["effect", "duration"],
+                )? {
+                    match key {
+                        "effect" => {
+                            effect = Some(value);
+                        }
+                        _ => {
+                            duration_ms = number(key, &value)?;
+                        }
+                    }
+                }
+
+                match required("transition", "effect", effect)?.as_str() {
+                    "none" => Event::TransitionMarker { transition: None },
+                    effect => Event::TransitionMarker {
+                        transition: Some(Transition {
+                            effect: effect.to_string(),
+                            duration_ms,
+                        }),
+                    },
+                }
+            }
+            ("bgcolor", color) => {
+                let color = tokenizer::text(color);
+
+                if color.is_empty() {
+                    return Err(DirectiveError::new("`bgcolor` has no color"));
+                }
+
+                Event::CPageMarker { color }
+            }
+            ("bgimage", properties) => {
+                let mut path = None;
+                let mut zoom = None;
+                let mut pan = None;
+
+                for (key, value) in self::properties(
+                    "bgimage",
+                    properties,
+                    Some("path"),
+                    &["path", "zoom", "pan"],
+                )? {
+                    match key {
+                        "path" => {
+                            path = Some(value);
+                        }
+                        "zoom" => {
+                            let (from, to) = value.split_once("..").unwrap_or((&value, &value));
+                            let (from, to): (f64, f64) =
+                                (number(key, from.trim())?, number(key, to.trim())?);
+
+                            // zoompan clamps the zoom to 1..10, so smaller values are not shown
+                            if from < 1.0 || to < 1.0 {
+                                return Err(DirectiveError::new(format!(
+                                    "`zoom` must be 1 or more: `{value}`"
+                                ))
+                                .with_hint("the image fills the page at zoom=1"));
+                            }
+
+                            zoom = Some((from, to));
+                        }
+                        _ => {
+                            pan = Some(match value.as_str() {
+                                "center" => Pan::Center,
+                                "left" => Pan::Left,
+                                "right" => Pan::Right,
+                                "up" => Pan::Up,
+                                "down" => Pan::Down,
+                                _ => {
+                                    return Err(DirectiveError::new(format!(
+                                        "unknown pan direction `{value}`"
+                                    ))
+                                    .with_hint("expected one of: center, left, right, up, down"))
+                                }
+                            });
+                        }
+                    }
+                }
+
+                let ken_burns = match (zoom, pan) {
+                    (None, None) => None,
+                    (zoom, pan) => {
+                        let (zoom_from, zoom_to) = zoom.unwrap_or((1.2, 1.2));
+
+                        Some(KenBurns {
+                            zoom_from,
+                            zoom_to,
+                            pan: pan.unwrap_or(Pan::Center),
+                        })
+                    }
+                };
+
+                Event::IPageMarker {
+                    path: resource_path(dir, &required("bgimage", "path", path)?),
+                    ken_burns,
+                }
+            }
+            ("sound_effect", properties) => {
+                let mut path = None;
+                let mut volume = 100.0f32;
+
+                for (key, value) in
+                    self::properties("sound_effect", properties, None, &["path", "volume"])?
+                {
+                    match key {
+                        "path" => {
+                            path = Some(value);
+                        }
+                        _ => {
+                            volume = number(key, &value)?;
+                        }
+                    }
+                }
+
+                Event::SoundEffect(RawFgSoundEvent {
+                    path: resource_path(dir, &required("sound_effect", "path", path)?),
+                    volume,
+                })
+            }
+            ("video", properties) => {
+                let mut path = None;
+                let mut volume = 100.0f32;
+                let mut start = None;
+                let mut end = None;
+
+                for (key, value) in self::properties(
+                    "video",
+                    properties,
+                    None,
+                    &["path", "volume", "start", "end"],
+                )? {
+                    match key {
+                        "path" => {
+                            path = Some(value);
+                        }
+                        "volume" => {
+                            volume = number(key, &value)?;
+                        }
+                        "start" => {
+                            start = Some(number(key, &value)?);
+                        }
+                        _ => {
+                            end = Some(number(key, &value)?);
+                        }
+                    }
+                }
+
+                Event::VPageMarker(RawVideoEvent {
+                    path: resource_path(dir, &required("video", "path", path)?),
+                    volume,
+                    start,
+                    end,
+                })
+            }
+            ("bgm", properties) => {
+                let mut path = None;
+                let mut volume = 100.0f32;
+                let mut duck = None;
+                let mut fade_in_ms = None;
+                let mut fade_out_ms = None;
+                let mut crossfade_ms = None;
+
+                for (key, value) in self::properties(
+                    "bgm",
+                    properties,
+                    None,
+                    &["path", "volume", "duck", "fade_in", "fade_out", "crossfade"],
+                )? {
+                    match key {
+                        "path" => {
+                            path = Some(value);
+                        }
+                        "volume" => {
+                            volume = number(key, &value)?;
+                        }
+                        "duck" => {
+                            duck = Some(number(key, &value)?);
+                        }
+                        "fade_in" => {
+                            fade_in_ms = Some(number(key, &value)?);
+                        }
+                        "fade_out" => {
+                            fade_out_ms = Some(number(key, &value)?);
+                        }
+                        _ => {
+                            crossfade_ms = Some(number(key, &value)?);
+                        }
+                    }
+                }
+
+                let path = match required("bgm", "path", path)?.as_str() {
+                    "none" => None,
+                    path => Some(resource_path(dir, path)),
+                };
+
+                Event::MVBGMMarker(Bgm {
+                    path,
+                    volume,
+                    duck,
+                    fade_in_ms,
+                    fade_out_ms,
+                    crossfade_ms,
+                    gain_db: 0.0,
+                })
+            }
+            ("include", properties) => {
+                let mut path = None;
+
+                let properties = self::properties("include", properties, Some("path"), &["path"])?;
+
+                for (_, value) in properties {
+                    path = Some(value);
+                }
+
+                let path = resource_path(dir, &required("include", "path", path)?);
+
+                return Ok(Some(Directive::Include(path)));
+            }
+            (key, _) => {
+                return Err(DirectiveError::new(format!("unknown directive `{key}`"))
+                    .with_hint(suggest(key, DIRECTIVES)));
+            }
+        };
+
+        Ok(Some(Directive::Event(event)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(s: &str) -> Result<Vec<(&'static str, String)>, String> {
+        properties("bgm", s, Some("path"), &["path", "volume"]).map_err(|e| e.message)
+    }
+
+    fn pairs(pairs: &[(&'static str, &str)]) -> Result<Vec<(&'static str, String)>, String> {
+        Ok(pairs.iter().map(|(k, v)| (*k, v.to_string())).collect())
+    }
+
+    #[test]
+    fn properties_unquoted_forms() {
+        assert_eq!(props("a.mp3"), pairs(&[("path", "a.mp3")]));
+        assert_eq!(
+            props("path=a.mp3, volume=50"),
+            pairs(&[("path", "a.mp3"), ("volume", "50")])
+        );
+        assert_eq!(
+            props("a.mp3,volume=50"),
+            pairs(&[("path", "a.mp3"), ("volume", "50")])
+        );
+        assert_eq!(props("path=a=b.mp3"), pairs(&[("path", "a=b.mp3")]));
+        assert_eq!(props("path=C:/a b.mp3"), pairs(&[("path", "C:/a b.mp3")]));
+    }
+
+    #[test]
+    fn properties_quoted_values() {
+        assert_eq!(props(r#"path="a,b.mp3""#), pairs(&[("path", "a,b.mp3")]));
+        assert_eq!(props(r#""a,b.mp3""#), pairs(&[("path", "a,b.mp3")]));
+        assert_eq!(props(r"path='a\b.mp3'"), pairs(&[("path", r"a\b.mp3")]));
+    }
+
+    #[test]
+    fn properties_errors() {
+        assert_eq!(
+            props("path=a.mp3,volme=50"),
+            Err("unknown property `volme` for `bgm`".to_string())
+        );
+        assert_eq!(
+            props("path=a.mp3,50"),
+            Err("malformed property `50` for `bgm`".to_string())
+        );
+        assert_eq!(
+            props("path="),
+            Err("malformed property `path=` for `bgm`".to_string())
+        );
+    }
+
+    fn texts(lines: &[&str]) -> Vec<Result<String, String>> {
+        Directive::try_from_lines(Path::new("/doc"), "default", lines)
+            .into_iter()
+            .map(|(_, directive)| match directive {
+                Ok(Directive::Event(Event::Voice(voice))) => Ok(voice.text),
+                Ok(directive) => Err(format!("{directive:?}")),
+                Err(e) => Err(e.message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn speak_block_dedents_lines() {
+        assert_eq!(
+            texts(&["speak: |", "    Hello,", "      world.", "", "    Bye."]),
+            [Ok("Hello,\n  world.\n\nBye.".to_string())]
+        );
+    }
+
+    #[test]
+    fn speak_block_with_full_width_space() {
+        assert_eq!(
+            texts(&["speak: |", "  こんにちは", "  \u{3000}世界"]),
+            [Ok("こんにちは\n\u{3000}世界".to_string())]
+        );
+    }
+
+    #[test]
+    fn speak_block_ends_at_full_width_indent() {
+        let texts = texts(&["speak: |", "  a", "\u{3000}b"]);
+
+        assert_eq!(texts[0], Ok("a".to_string()));
+        assert!(texts[1].is_err());
+    }
+
+    fn zoom(value: &str) -> Result<(f64, f64), String> {
+        match Directive::try_from_str(
+            Path::new("/doc"),
+            "default",
+            &format!("bgimage: a.png, zoom={value}"),
+        ) {
+            Ok(Some(Directive::Event(Event::IPageMarker {
+                ken_burns: Some(ken_burns),
+                ..
+            }))) => Ok((ken_burns.zoom_from, ken_burns.zoom_to)),
+            Ok(directive) => Err(format!("{directive:?}")),
+            Err(e) => Err(e.message),
+        }
+    }
+
+    #[test]
+    fn bgimage_zoom() {
+        assert_eq!(zoom("1.5"), Ok((1.5, 1.5)));
+        assert_eq!(zoom("1..1.3"), Ok((1.0, 1.3)));
+        assert_eq!(
+            zoom("0.8"),
+            Err("`zoom` must be 1 or more: `0.8`".to_string())
+        );
+        assert_eq!(
+            zoom("1.2..0.5"),
+            Err("`zoom` must be 1 or more: `1.2..0.5`".to_string())
+        );
+    }
+}
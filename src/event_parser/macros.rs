@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::directive::{suggest, DirectiveError};
+
+/// `marpVideo.vars` value, used as `${name}`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub(super) enum Var {
+    Text(String),
+    Number(serde_yaml::Number),
+    Bool(bool),
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Var::Text(s) => write!(f, "{s}"),
+            Var::Number(n) => write!(f, "{n}"),
+            Var::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// `marpVideo.macros` value, a sequence of `mv` lines used as `@name`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub(super) enum Macro {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Macro {
+    fn lines(&self) -> Vec<&str> {
+        match self {
+            Macro::Text(s) => s.lines().collect(),
+            Macro::Lines(lines) => lines.iter().flat_map(|l| l.lines()).collect(),
+        }
+    }
+}
+
+/// Replace `${name}` with the variable; `$${` is a literal `${`
+fn substitute(line: &str, vars: &HashMap<String, Var>) -> Result<String, DirectiveError> {
+    let mut out = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('}') else {
+            return Err(DirectiveError::new("unterminated `${`").with_hint("${name}"));
+        };
+
+        let name = rest[start + 2..start + end].trim();
+
+        let Some(var) = vars.get(name) else {
+            let names: Vec<&str> = vars.keys().map(String::as_str).collect();
+
+            return Err(DirectiveError::new(format!("undefined variable `{name}`"))
+                .with_hint(suggest(name, &names)));
+        };
+
+        out.push_str(&var.to_string());
+        rest = &rest[start + end + 1..];
+    }
+
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn expand_line<'a>(
+    line: &'a str,
+    vars: &HashMap<String, Var>,
+    macros: &'a HashMap<String, Macro>,
+    stack: &mut Vec<&'a str>,
+    out: &mut Vec<Result<String, DirectiveError>>,
+) {
+    let Some(name) = line.trim().strip_prefix('@').map(str::trim) else {
+        out.push(substitute(line, vars));
+        return;
+    };
+
+    let Some(m) = macros.get(name) else {
+        let names: Vec<&str> = macros.keys().map(String::as_str).collect();
+
+        out.push(Err(DirectiveError::new(format!(
+            "undefined macro `@{name}`"
+        ))
+        .with_hint(suggest(name, &names))));
+        return;
+    };
+
+    if stack.contains(&name) {
+        let cycle: Vec<String> = stack
+            .iter()
+            .chain([&name])
+            .map(|name| format!("@{name}"))
+            .collect();
+
+        out.push(Err(DirectiveError::new(format!(
+            "recursive macro: {}",
+            cycle.join(" -> ")
+        ))));
+        return;
+    }
+
+    stack.push(name);
+
+    for line in m.lines() {
+        expand_line(line, vars, macros, stack, out);
+    }
+
+    stack.pop();
+}
+
+/// Expand `@macro` lines and `${var}`s, with the index of the line each one comes from
+pub(super) fn expand(
+    lines: &[&str],
+    vars: &HashMap<String, Var>,
+    macros: &HashMap<String, Macro>,
+) -> Vec<(usize, Result<String, DirectiveError>)> {
+    let mut expanded = vec![];
+
+    for (n, line) in lines.iter().enumerate() {
+        let mut out = vec![];
+        expand_line(line, vars, macros, &mut vec![], &mut out);
+        expanded.extend(out.into_iter().map(|line| (n, line)));
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_yaml(
+        lines: &[&str],
+        vars: &str,
+        macros: &str,
+    ) -> Vec<(usize, Result<String, String>)> {
+        let vars: HashMap<String, Var> = serde_yaml::from_str(vars).unwrap();
+        let macros: HashMap<String, Macro> = serde_yaml::from_str(macros).unwrap();
+
+        expand(lines, &vars, &macros)
+            .into_iter()
+            .map(|(n, line)| (n, line.map_err(|e| e.message)))
+            .collect()
+    }
+
+    #[test]
+    fn substitutes_vars() {
+        assert_eq!(
+            expand_yaml(
+                &["bgm: path=${dir}/a.mp3,volume=${ volume }"],
+                "{dir: music, volume: 50}",
+                "{}"
+            ),
+            [(0, Ok("bgm: path=music/a.mp3,volume=50".to_string()))]
+        );
+    }
+
+    #[test]
+    fn double_dollar_is_literal() {
+        assert_eq!(
+            expand_yaml(&["speak: costs $${price} ${x}"], "{x: true}", "{}"),
+            [(0, Ok("speak: costs ${price} true".to_string()))]
+        );
+    }
+
+    #[test]
+    fn undefined_var() {
+        assert_eq!(
+            expand_yaml(&["speak: ${nmae}"], "{name: x}", "{}"),
+            [(0, Err("undefined variable `nmae`".to_string()))]
+        );
+    }
+
+    #[test]
+    fn expands_macros_recursively() {
+        let macros =
+            "{intro: [\"speak: hi ${who}\", \"@outro\"], outro: \"blank: 300\\nspeak: bye\"}";
+
+        assert_eq!(
+            expand_yaml(&["transition: fade", "  @intro"], "{who: all}", macros),
+            [
+                (0, Ok("transition: fade".to_string())),
+                (1, Ok("speak: hi all".to_string())),
+                (1, Ok("blank: 300".to_string())),
+                (1, Ok("speak: bye".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_macro() {
+        let macros = "{a: [\"speak: a\", \"@b\"], b: [\"@a\"]}";
+
+        assert_eq!(
+            expand_yaml(&["@a"], "{}", macros),
+            [
+                (0, Ok("speak: a".to_string())),
+                (0, Err("recursive macro: @a -> @b -> @a".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_macro() {
+        assert_eq!(
+            expand_yaml(&["@nope"], "{}", "{}"),
+            [(0, Err("undefined macro `@nope`".to_string()))]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::event::Event;
+
+use super::{RawFgSoundEvent, RawVideoEvent, RawVoiceEvent};
+
+/// `300ms` / `1.5s`
+fn pause_ms(s: &str) -> Option<usize> {
+    let s = s.trim();
+
+    let ms = if let Some(ms) = s.strip_suffix("ms") {
+        ms.trim().parse::<f64>().ok()?
+    } else {
+        s.strip_suffix('s')?.trim().parse::<f64>().ok()? * 1000.0
+    };
+
+    (ms.is_finite() && ms >= 0.0).then_some(ms.round() as usize)
+}
+
+/// Expand `[pause 300ms]` in the text of a voice into voices and blanks
+///
+/// Any other `[...]` is left in the text as it is (see `emphasize` for `[em ...]`).
+pub(super) fn expand(
+    event: Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>,
+) -> Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>> {
+    let Event::Voice(voice) = event else {
+        return vec![event];
+    };
+
+    let mut events = vec![];
+    let mut text = String::new();
+    let mut rest = voice.text.as_str();
+
+    let flush = |text: &mut String, events: &mut Vec<_>| {
+        let t = std::mem::take(text);
+
+        if !t.trim().is_empty() {
+            events.push(Event::Voice(RawVoiceEvent {
+                text: t.trim().to_string(),
+                profile: voice.profile.clone(),
+                speech: None,
+            }));
+        }
+    };
+
+    while let Some(open) = rest.find("[pause") {
+        let (before, tag) = rest.split_at(open);
+        text.push_str(before);
+
+        let pause = tag
+            .find(']')
+            .and_then(|close| Some((pause_ms(&tag["[pause".len()..close])?, close)));
+
+        match pause {
+            Some((ms, close)) => {
+                flush(&mut text, &mut events);
+                events.push(Event::BlankMs(ms));
+                rest = &tag[close + 1..];
+            }
+            None => {
+                text.push_str("[pause");
+                rest = &tag["[pause".len()..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+    flush(&mut text, &mut events);
+
+    events
+}
+
+/// Replace `[em text]` with the text in `template` (`{}`), or with the text itself
+///
+/// Invalid markup such as `[em]` or a missing `]` is left in the text as it is.
+pub(super) fn emphasize(text: &str, template: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find("[em ") {
+        let (before, tag) = rest.split_at(open);
+        out.push_str(before);
+
+        let inner = tag
+            .find(']')
+            .map(|close| (tag["[em ".len()..close].trim(), close))
+            .filter(|(inner, _)| !inner.is_empty());
+
+        match inner {
+            Some((inner, close)) => {
+                match template {
+                    Some(template) => out.push_str(&template.replace("{}", inner)),
+                    None => out.push_str(inner),
+                }
+                rest = &tag[close + 1..];
+            }
+            None => {
+                out.push_str("[em ");
+                rest = &tag["[em ".len()..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(text: &str) -> Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent> {
+        Event::Voice(RawVoiceEvent {
+            text: text.to_string(),
+            profile: "default".to_string(),
+            speech: None,
+        })
+    }
+
+    fn describe(events: &[Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                Event::Voice(voice) => voice.text.clone(),
+                Event::BlankMs(ms) => format!("{ms}ms"),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_pauses() {
+        let events = expand(voice("Hello, [pause 300ms] world. [pause 1.5s]"));
+
+        assert_eq!(describe(&events), ["Hello,", "300ms", "world.", "1500ms"]);
+    }
+
+    #[test]
+    fn expand_keeps_invalid_markup() {
+        let events = expand(voice("a [pause soon] b [pause 1s"));
+
+        assert_eq!(describe(&events), ["a [pause soon] b [pause 1s"]);
+    }
+
+    #[test]
+    fn emphasize_strips_tags() {
+        assert_eq!(emphasize("It's [em really] fast", None), "It's really fast");
+    }
+
+    #[test]
+    fn emphasize_with_template() {
+        assert_eq!(
+            emphasize("It's [em really] fast", Some("<emphasis>{}</emphasis>")),
+            "It's <emphasis>really</emphasis> fast"
+        );
+    }
+
+    #[test]
+    fn emphasize_keeps_invalid_markup() {
+        assert_eq!(
+            emphasize("[em] [emoji x] [em open", None),
+            "[em] [emoji x] [em open"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use serde_yaml::Value;
+
+const GLOBAL_DIRECTIVES: &[&str] = &[
+    "theme",
+    "style",
+    "headingDivider",
+    "lang",
+    "size",
+    "math",
+    "title",
+    "description",
+    "author",
+    "image",
+    "keywords",
+    "url",
+];
+
+const LOCAL_DIRECTIVES: &[&str] = &[
+    "paginate",
+    "header",
+    "footer",
+    "class",
+    "backgroundColor",
+    "backgroundImage",
+    "backgroundPosition",
+    "backgroundRepeat",
+    "backgroundSize",
+    "color",
+    "transition",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Scope {
+    /// Whole document, the last definition wins
+    Global,
+
+    /// The page and the following pages
+    Local,
+
+    /// `_` prefixed, the page only
+    Spot,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct MarpDirective {
+    pub scope: Scope,
+    pub name: String,
+    pub value: Value,
+}
+
+/// Marp directives in an HTML comment, or `None` if Marp takes the comment as a presenter note
+pub(super) fn parse_comment(comment: &str) -> Option<Vec<MarpDirective>> {
+    let content = comment.strip_prefix("<!--")?.strip_suffix("-->")?;
+
+    let Ok(Value::Mapping(mapping)) = serde_yaml::from_str(content) else {
+        return None;
+    };
+
+    let directives: Vec<MarpDirective> = mapping
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = key.as_str()?;
+
+            let (scope, name) = match key.strip_prefix('_').or_else(|| key.strip_prefix('$')) {
+                Some(name) if key.starts_with('_') && LOCAL_DIRECTIVES.contains(&name) => {
+                    (Scope::Spot, name)
+                }
+                // `$theme` is the deprecated form of global directives
+                Some(name) if key.starts_with('$') && GLOBAL_DIRECTIVES.contains(&name) => {
+                    (Scope::Global, name)
+                }
+                _ if GLOBAL_DIRECTIVES.contains(&key) => (Scope::Global, key),
+                _ if LOCAL_DIRECTIVES.contains(&key) => (Scope::Local, key),
+                _ => return None,
+            };
+
+            Some(MarpDirective {
+                scope,
+                name: name.to_string(),
+                value,
+            })
+        })
+        .collect();
+
+    if directives.is_empty() {
+        None
+    } else {
+        Some(directives)
+    }
+}
+
+/// `headingDivider`: heading depths which start a new page
+#[derive(Debug, Clone, Default)]
+pub(super) struct HeadingDivider(Vec<u64>);
+
+impl HeadingDivider {
+    /// `None` for the values Marp ignores
+    pub fn from_yaml(value: &Value) -> Option<Self> {
+        let depth = |v: &Value| v.as_u64().filter(|d| (1..=6).contains(d));
+
+        match value {
+            Value::Bool(false) => Some(Self::default()),
+            Value::Number(_) => depth(value).map(|d| Self((1..=d).collect())),
+            Value::Sequence(depths) => depths.iter().map(depth).collect::<Option<_>>().map(Self),
+            _ => None,
+        }
+    }
+
+    pub fn splits(&self, depth: u8) -> bool {
+        self.0.contains(&u64::from(depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heading_divider(yaml: &str) -> Option<Vec<u8>> {
+        let divider = HeadingDivider::from_yaml(&serde_yaml::from_str(yaml).unwrap())?;
+        Some((1..=6).filter(|&d| divider.splits(d)).collect())
+    }
+
+    #[test]
+    fn heading_divider_values() {
+        assert_eq!(heading_divider("2"), Some(vec![1, 2]));
+        assert_eq!(heading_divider("[1, 3]"), Some(vec![1, 3]));
+        assert_eq!(heading_divider("false"), Some(vec![]));
+        assert_eq!(heading_divider("0"), None);
+        assert_eq!(heading_divider("7"), None);
+        assert_eq!(heading_divider("[1, 7]"), None);
+        assert_eq!(heading_divider("true"), None);
+    }
+
+    #[test]
+    fn comments() {
+        let directives =
+            parse_comment("<!--\nheadingDivider: 2\n_class: lead\n$theme: gaia\n-->").unwrap();
+        let scopes: Vec<_> = directives
+            .iter()
+            .map(|d| (d.scope, d.name.as_str()))
+            .collect();
+
+        assert_eq!(
+            scopes,
+            [
+                (Scope::Global, "headingDivider"),
+                (Scope::Spot, "class"),
+                (Scope::Global, "theme"),
+            ]
+        );
+
+        assert!(parse_comment("<!-- Presenter note: say hello -->").is_none());
+        assert!(parse_comment("<!-- just a note -->").is_none());
+    }
+}
@@ -0,0 +1,1164 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::environment::{Environment, ProfileOverrides};
+use crate::event::Event;
+use crate::pronunciation::Dictionary;
+
+mod diagnostic;
+pub use diagnostic::{Diagnostic, Location, Severity};
+
+mod directive;
+use directive::Directive;
+
+mod macros;
+use macros::{Macro, Var};
+
+mod markup;
+
+mod marp_directive;
+use marp_directive::{HeadingDivider, Scope};
+
+mod sentence;
+
+mod tokenizer;
+
+#[derive(Debug, Clone)]
+pub struct RawFgSoundEvent {
+    pub path: PathBuf,
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawVideoEvent {
+    pub path: PathBuf,
+    pub volume: f32,
+
+    /// Seconds from the beginning of the clip
+    pub start: Option<f64>,
+
+    /// Seconds from the beginning of the clip
+    pub end: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawVoiceEvent {
+    /// As written, for subtitles and captions
+    pub text: String,
+    pub profile: String,
+
+    /// Text for TTS if the pronunciation dictionary changed it
+    pub speech: Option<String>,
+}
+
+impl RawVoiceEvent {
+    pub fn speech(&self) -> &str {
+        self.speech.as_deref().unwrap_or(&self.text)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RawDocElement {
+    /// Marp Page Marker (with the location of the separator)
+    MarpPageMarker(Location),
+
+    /// Marp Content Marker
+    MarpContentMarker,
+
+    /// Marp Heading (Content Marker with its text)
+    MarpHeading(String),
+
+    /// Marp Video Events
+    MVEvent(Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>),
+
+    /// Events of an included document
+    Included(Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct DocEvents {
+    pub events: Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>>,
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// The document and the ones it includes, each rendered by Marp separately
+    pub sources: Vec<PathBuf>,
+
+    /// Where the Marp pages after the first (implicit) one start, per source
+    pub page_separators: Vec<Vec<Location>>,
+
+    /// Profile values set by the frontmatter of the document (not the included ones)
+    pub overrides: ProfileOverrides,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("Markdown parse error: {:?}", .0)]
+    Markdown(markdown::message::Message),
+
+    #[error("Frontmatter is not exist")]
+    FrontmatterIsNotExist,
+
+    #[error("Invalid frontmatter YAML: {:?}", .0)]
+    Frontmatter(serde_yaml::Error),
+
+    #[error("Non marp document")]
+    NonMarpDocument,
+
+    #[error("Non marpVideo document")]
+    NonMarpVideoDocument,
+
+    #[error("First element is not page\n  --> {0}")]
+    FirstElementIsNotPage(Location),
+
+    #[error("Document has no content")]
+    EmptyDocument,
+
+    #[error("Unexpected {kind} node in the document\n  --> {location}")]
+    UnexpectedNode {
+        kind: &'static str,
+        location: Location,
+    },
+
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Recursive include: {cycle}\n  --> {location}")]
+    IncludeCycle { cycle: String, location: Location },
+
+    #[error("{source}\n  included from {location}")]
+    Include {
+        location: Location,
+        source: Box<ParseError>,
+    },
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct MarpVideoOptions {
+    /// Speak presenter notes (`<!-- ... -->` except Marp directives)
+    #[serde(default)]
+    notes_as_speech: bool,
+
+    /// On top of the dictionaries in the config
+    #[serde(default)]
+    pronunciation: BTreeMap<String, String>,
+
+    /// `${name}` in `mv` lines
+    #[serde(default)]
+    vars: HashMap<String, Var>,
+
+    /// `@name` line in `mv` blocks
+    #[serde(default)]
+    macros: HashMap<String, Macro>,
+
+    /// Voice profile of `speak` and presenter notes (the including document's by default)
+    voice: Option<String>,
+
+    // The rest override the profile of the config
+    width: Option<usize>,
+    height: Option<usize>,
+    framerate: Option<usize>,
+    video_container: Option<String>,
+    ffmpeg_args: Option<Vec<String>>,
+    marp_args: Option<Vec<String>>,
+    bgm: Option<BgmOptions>,
+}
+
+/// `marpVideo: { bgm: { fadeInMs: 1000 } }`
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BgmOptions {
+    fade_in_ms: Option<usize>,
+    fade_out_ms: Option<usize>,
+    crossfade_ms: Option<usize>,
+}
+
+impl MarpVideoOptions {
+    fn profile_overrides(&self) -> ProfileOverrides {
+        let bgm = self.bgm.as_ref();
+
+        ProfileOverrides {
+            voice: self.voice.clone(),
+            width: self.width,
+            height: self.height,
+            framerate: self.framerate,
+            video_container: self.video_container.clone(),
+            ffmpeg_args: self.ffmpeg_args.clone(),
+            marp_args: self.marp_args.clone(),
+            bgm_fade_in_ms: bgm.and_then(|bgm| bgm.fade_in_ms),
+            bgm_fade_out_ms: bgm.and_then(|bgm| bgm.fade_out_ms),
+            bgm_crossfade_ms: bgm.and_then(|bgm| bgm.crossfade_ms),
+        }
+    }
+
+    /// Any override of the output (other than `voice`)
+    fn overrides_output(&self) -> bool {
+        self.width.is_some()
+            || self.height.is_some()
+            || self.framerate.is_some()
+            || self.video_container.is_some()
+            || self.ffmpeg_args.is_some()
+            || self.marp_args.is_some()
+            || self.bgm.is_some()
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frontmatter {
+    /// `true`, or the options like `{ notesAsSpeech: true }`
+    marp_video: Option<serde_yaml::Value>,
+    marp: Option<bool>,
+
+    heading_divider: Option<serde_yaml::Value>,
+}
+
+impl DocEvents {
+    pub fn parse(env: &Environment, s: &str) -> Result<Self, ParseError> {
+        let mut doc = Self {
+            events: vec![],
+            diagnostics: vec![],
+            sources: vec![],
+            page_separators: vec![],
+            overrides: ProfileOverrides::default(),
+        };
+
+        let path = env.md_path();
+        let voice = env.default_voice_profile();
+        doc.events = doc.parse_source(env, path, s, voice, &mut vec![canonical(path)])?;
+
+        if doc.events.is_empty() {
+            return Err(ParseError::EmptyDocument);
+        }
+
+        Ok(doc)
+    }
+
+    /// Parse a document as a new source, with the ones it includes
+    ///
+    /// `voice` is the voice profile of the including document (or of the profile),
+    /// and `stack` is the chain of includes down to this document (canonical paths).
+    fn parse_source(
+        &mut self,
+        env: &Environment,
+        path: &Path,
+        s: &str,
+        voice: &str,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>>, ParseError> {
+        use markdown::{mdast::Node, Constructs, ParseOptions};
+
+        let md_ast = markdown::to_mdast(
+            s,
+            &ParseOptions {
+                constructs: Constructs {
+                    frontmatter: true,
+                    ..Constructs::gfm()
+                },
+                ..ParseOptions::default()
+            },
+        )
+        .map_err(ParseError::Markdown)?;
+
+        let md_nodes = md_ast.children().ok_or(ParseError::FrontmatterIsNotExist)?;
+        let first_node = md_nodes.first().ok_or(ParseError::FrontmatterIsNotExist)?;
+
+        let Node::Yaml(yaml) = first_node else {
+            return Err(ParseError::FrontmatterIsNotExist);
+        };
+
+        let frontmatter: Frontmatter =
+            serde_yaml::from_str(&yaml.value).map_err(ParseError::Frontmatter)?;
+
+        if frontmatter.marp != Some(true) {
+            return Err(ParseError::NonMarpDocument);
+        }
+
+        let mut options = match frontmatter.marp_video {
+            Some(serde_yaml::Value::Bool(true)) => MarpVideoOptions::default(),
+            // Deserialized here to keep the error of the options, e.g. an unknown key
+            Some(options @ serde_yaml::Value::Mapping(_)) => {
+                serde_yaml::from_value(options).map_err(ParseError::Frontmatter)?
+            }
+            _ => {
+                return Err(ParseError::NonMarpVideoDocument);
+            }
+        };
+
+        let source = self.sources.len();
+        self.sources.push(path.to_path_buf());
+        self.page_separators.push(vec![]);
+
+        if source == 0 {
+            self.overrides = options.profile_overrides();
+        } else if options.overrides_output() {
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                location: location(path, yaml.position.as_ref()),
+                message: "output settings of an included document are ignored".to_string(),
+                source: None,
+                hint: Some("set them in the frontmatter of the including document".to_string()),
+            });
+        }
+
+        options.voice.get_or_insert_with(|| voice.to_string());
+
+        let heading_divider = match &frontmatter.heading_divider {
+            Some(value) => {
+                let location = location(path, yaml.position.as_ref());
+                validate_heading_divider(value, location, &mut self.diagnostics).unwrap_or_default()
+            }
+            None => HeadingDivider::default(),
+        };
+
+        let raw_document = self.md_nodes_to_raw_doc_elements(
+            env,
+            path,
+            md_nodes,
+            heading_divider,
+            &options,
+            stack,
+        )?;
+
+        // Events of the included documents are already expanded with their own options
+        let mut raw_document: Vec<_> = raw_document
+            .into_iter()
+            .flat_map(|element| match element {
+                RawDocElement::MVEvent(event) => markup::expand(event)
+                    .into_iter()
+                    .map(RawDocElement::MVEvent)
+                    .collect(),
+                element => vec![element],
+            })
+            .collect();
+
+        for e in Dictionary::default().merge(&options.pronunciation) {
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                location: location(path, yaml.position.as_ref()),
+                message: e.to_string(),
+                source: Some(e.key),
+                hint: Some("`/.../` keys are regular expressions".to_string()),
+            });
+        }
+
+        let mut dictionaries = HashMap::new();
+
+        for element in &mut raw_document {
+            if let RawDocElement::MVEvent(Event::Voice(voice)) = element {
+                let dictionary = dictionaries
+                    .entry(voice.profile.clone())
+                    .or_insert_with(|| {
+                        let mut dictionary = env.pronunciation(&voice.profile);
+                        dictionary.merge(&options.pronunciation);
+                        dictionary
+                    });
+
+                let speech = dictionary.apply(&voice.text);
+                let speech = markup::emphasize(&speech, env.tts_emphasis(&voice.profile));
+
+                voice.text = markup::emphasize(&voice.text, None);
+
+                if speech != voice.text {
+                    voice.speech = Some(speech);
+                }
+            }
+        }
+
+        let (strctured_doc, page_separators) = parse_page_structure(&raw_document, source);
+        self.page_separators[source] = page_separators;
+
+        Ok(strctured_doc)
+    }
+
+    /// Parse the document of `include:`
+    fn include(
+        &mut self,
+        env: &Environment,
+        path: &Path,
+        voice: &str,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>>, ParseError> {
+        let s = std::fs::read_to_string(path).map_err(|source| ParseError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        stack.push(canonical(path));
+        let events = self.parse_source(env, path, &s, voice, stack);
+        stack.pop();
+
+        events
+    }
+
+    /// Cross-check the pages of each source with the number of images Marp rendered for it
+    pub fn check_page_count(&self, rendered: &[usize]) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        for (source, &rendered) in rendered.iter().enumerate() {
+            let Some(parsed) = self
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::MPageMarker {
+                        source: s,
+                        marp_page_nth,
+                        ..
+                    } if *s == source => Some(*marp_page_nth),
+                    _ => None,
+                })
+                .max()
+            else {
+                continue;
+            };
+
+            let page_separators = &self.page_separators[source];
+
+            let (separator, hint) = match parsed.cmp(&rendered) {
+                std::cmp::Ordering::Equal => continue,
+                // The separator of the first page Marp didn't render
+                std::cmp::Ordering::Greater => (
+                    rendered.checked_sub(1).and_then(|n| page_separators.get(n)),
+                    "Marp doesn't start a new page here",
+                ),
+                // Marp split some page after the last separator found
+                std::cmp::Ordering::Less => (
+                    page_separators.last(),
+                    "Marp starts more pages after here (e.g. by a heading with headingDivider)",
+                ),
+            };
+
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                location: separator
+                    .cloned()
+                    .unwrap_or_else(|| location(&self.sources[source], None)),
+                message: format!("document has {parsed} page(s), but Marp rendered {rendered}"),
+                source: None,
+                hint: Some(hint.to_string()),
+            });
+        }
+
+        diagnostics
+    }
+
+    fn md_nodes_to_raw_doc_elements(
+        &mut self,
+        env: &Environment,
+        path: &Path,
+        nodes: &[markdown::mdast::Node],
+        heading_divider: HeadingDivider,
+        options: &MarpVideoOptions,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<Vec<RawDocElement>, ParseError> {
+        use markdown::mdast::{Heading, Html, Node};
+
+        // headingDivider is a global directive, so the last one in the document wins wherever it is
+        let heading_divider = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Html(Html { value, position }) if !value.starts_with("<!--mv") => {
+                    let directives = marp_directive::parse_comment(value)?;
+                    Some((position, directives))
+                }
+                _ => None,
+            })
+            .flat_map(|(position, directives)| directives.into_iter().map(move |d| (position, d)))
+            .filter(|(_, d)| d.scope == Scope::Global && d.name == "headingDivider")
+            .fold(heading_divider, |acc, (position, d)| {
+                let location = location(path, position.as_ref());
+                validate_heading_divider(&d.value, location, &mut self.diagnostics).unwrap_or(acc)
+            });
+
+        // Resolved by parse_source
+        let voice = options.voice.as_deref().unwrap_or_default();
+
+        let mut raw_document = vec![];
+
+        for node in nodes.iter().skip(1) {
+            match node {
+                Node::ThematicBreak(_) => {
+                    let separator = location(path, node.position());
+                    raw_document.push(RawDocElement::MarpPageMarker(separator));
+                }
+
+                Node::Heading(Heading { depth, .. }) => {
+                    // Like Marp, never split before the first visible element of the document
+                    // (`mv` comments, including `include:`, are not rendered)
+                    let after_visible = raw_document.iter().any(|e| {
+                        !matches!(e, RawDocElement::MVEvent(_) | RawDocElement::Included(_))
+                    });
+
+                    if heading_divider.splits(*depth) && after_visible {
+                        let separator = location(path, node.position());
+                        raw_document.push(RawDocElement::MarpPageMarker(separator));
+                    }
+
+                    raw_document.push(RawDocElement::MarpHeading(node.to_string()));
+                }
+
+                Node::Html(Html { value, position })
+                    if value.starts_with("<!--mv") && value.ends_with("-->") =>
+                {
+                    let marpv_syntax = &value["<!--mv".len()..value.len() - "-->".len()];
+
+                    let marpv_lines: Vec<&str> = marpv_syntax.split('\n').collect();
+
+                    let mut errors = vec![];
+                    let mut expanded_lines = vec![];
+
+                    // Index in marpv_lines of each expanded line
+                    let mut origins = vec![];
+
+                    for (n, line) in macros::expand(&marpv_lines, &options.vars, &options.macros) {
+                        match line {
+                            Ok(line) => {
+                                expanded_lines.push(line);
+                                origins.push(n);
+                            }
+                            Err(e) => errors.push((n, e)),
+                        }
+                    }
+
+                    let expanded_lines: Vec<&str> =
+                        expanded_lines.iter().map(String::as_str).collect();
+
+                    let dir = path.parent().unwrap_or(Path::new("."));
+
+                    for (n, directive) in Directive::try_from_lines(dir, voice, &expanded_lines) {
+                        match directive {
+                            Ok(Directive::Event(event)) => {
+                                if !event.is_page() && !has_page(&raw_document) {
+                                    let n = origins[n];
+                                    return Err(ParseError::FirstElementIsNotPage(
+                                        mv_line_location(
+                                            path,
+                                            position.as_ref(),
+                                            n,
+                                            marpv_lines[n],
+                                        ),
+                                    ));
+                                }
+
+                                raw_document.push(RawDocElement::MVEvent(event));
+                            }
+                            Ok(Directive::Include(include)) => {
+                                let n = origins[n];
+                                let location =
+                                    mv_line_location(path, position.as_ref(), n, marpv_lines[n]);
+
+                                let canonical_include = canonical(&include);
+
+                                let start = stack.iter().position(|p| *p == canonical_include);
+
+                                if let Some(start) = start {
+                                    let cycle = stack[start..]
+                                        .iter()
+                                        .chain([&canonical_include])
+                                        .map(|p| p.display().to_string())
+                                        .collect::<Vec<_>>()
+                                        .join(" -> ");
+
+                                    return Err(ParseError::IncludeCycle { cycle, location });
+                                }
+
+                                let events =
+                                    self.include(env, &include, voice, stack).map_err(|e| {
+                                        ParseError::Include {
+                                            location,
+                                            source: Box::new(e),
+                                        }
+                                    })?;
+
+                                raw_document.push(RawDocElement::Included(events));
+                            }
+                            Err(e) => errors.push((origins[n], e)),
+                        }
+                    }
+
+                    errors.sort_by_key(|(n, _)| *n);
+
+                    for (n, e) in errors {
+                        let marpv_line = marpv_lines[n];
+
+                        self.diagnostics.push(Diagnostic {
+                            severity: Severity::Warning,
+                            location: mv_line_location(path, position.as_ref(), n, marpv_line),
+                            message: e.message,
+                            source: Some(marpv_line.trim().to_string()),
+                            hint: e.hint,
+                        });
+                    }
+                }
+
+                Node::Html(Html { value, position })
+                    if options.notes_as_speech
+                        && value.starts_with("<!--")
+                        && marp_directive::parse_comment(value).is_none() =>
+                {
+                    // `<!-->` and `<!--->` are complete comments in CommonMark
+                    let Some(note) = value
+                        .strip_prefix("<!--")
+                        .and_then(|v| v.strip_suffix("-->"))
+                    else {
+                        continue;
+                    };
+
+                    if !has_page(&raw_document) && !sentence::split(note).is_empty() {
+                        return Err(ParseError::FirstElementIsNotPage(location(
+                            path,
+                            position.as_ref(),
+                        )));
+                    }
+
+                    for sentence in sentence::split(note) {
+                        raw_document.push(RawDocElement::MVEvent(Event::Voice(RawVoiceEvent {
+                            text: sentence,
+                            profile: voice.to_string(),
+                            speech: None,
+                        })));
+                    }
+                }
+
+                Node::Html(Html { value, .. })
+                    if value.starts_with("<!--") || value.starts_with("<style>") =>
+                {
+                    // Marp directives and presenter notes are not rendered
+                }
+
+                Node::Html(_) => {
+                    raw_document.push(RawDocElement::MarpContentMarker);
+                }
+
+                Node::Blockquote(_)
+                | Node::FootnoteDefinition(_)
+                | Node::MdxJsxTextElement(_)
+                | Node::MdxJsxFlowElement(_)
+                | Node::List(_)
+                | Node::MdxjsEsm(_)
+                | Node::Break(_)
+                | Node::InlineCode(_)
+                | Node::InlineMath(_)
+                | Node::Delete(_)
+                | Node::Emphasis(_)
+                | Node::MdxTextExpression(_)
+                | Node::FootnoteReference(_)
+                | Node::Image(_)
+                | Node::ImageReference(_)
+                | Node::Link(_)
+                | Node::LinkReference(_)
+                | Node::Strong(_)
+                | Node::Text(_)
+                | Node::Code(_)
+                | Node::Math(_)
+                | Node::MdxFlowExpression(_)
+                | Node::Table(_)
+                | Node::Definition(_)
+                | Node::Paragraph(_) => {
+                    raw_document.push(RawDocElement::MarpContentMarker);
+                }
+
+                Node::Root(_)
+                | Node::Toml(_)
+                | Node::Yaml(_)
+                | Node::TableCell(_)
+                | Node::TableRow(_)
+                | Node::ListItem(_) => {
+                    let kind = match node {
+                        Node::Root(_) => "root",
+                        Node::Toml(_) => "TOML frontmatter",
+                        Node::Yaml(_) => "YAML frontmatter",
+                        Node::TableCell(_) => "table cell",
+                        Node::TableRow(_) => "table row",
+                        _ => "list item",
+                    };
+
+                    return Err(ParseError::UnexpectedNode {
+                        kind,
+                        location: location(path, node.position()),
+                    });
+                }
+            }
+        }
+
+        Ok(raw_document)
+    }
+}
+
+/// Validate a `headingDivider` value, ignoring (and reporting) the ones Marp ignores
+fn validate_heading_divider(
+    value: &serde_yaml::Value,
+    location: Location,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<HeadingDivider> {
+    let heading_divider = HeadingDivider::from_yaml(value);
+
+    if heading_divider.is_none() {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            location,
+            message: "invalid `headingDivider`, ignored".to_string(),
+            source: serde_yaml::to_string(value)
+                .ok()
+                .map(|v| v.trim().to_string()),
+            hint: Some("expected a depth 1-6, an array of depths or `false`".to_string()),
+        });
+    }
+
+    heading_divider
+}
+
+fn location(path: &Path, position: Option<&markdown::unist::Position>) -> Location {
+    // The beginning of the file if unknown
+    let (line, column) = position.map_or((1, 1), |p| (p.start.line, p.start.column));
+
+    Location {
+        path: path.to_path_buf(),
+        line,
+        column,
+    }
+}
+
+/// For finding the same document through different paths
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Location of the `n`th line in `<!--mv ... -->`, pointing at its first non-blank character
+fn mv_line_location(
+    path: &Path,
+    position: Option<&markdown::unist::Position>,
+    n: usize,
+    line: &str,
+) -> Location {
+    let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+    let location = location(path, position);
+
+    if n == 0 {
+        Location {
+            column: location.column + "<!--mv".len() + indent,
+            ..location
+        }
+    } else {
+        Location {
+            line: location.line + n,
+            column: 1 + indent,
+            ..location
+        }
+    }
+}
+
+/// Whether the elements start a page, i.e. events after them are not the first ones
+fn has_page(elements: &[RawDocElement]) -> bool {
+    elements.iter().any(|e| match e {
+        RawDocElement::MVEvent(event) => event.is_page(),
+        RawDocElement::Included(events) => !events.is_empty(),
+        _ => true,
+    })
+}
+
+fn parse_page_structure(
+    elements: &[RawDocElement],
+    source: usize,
+) -> (
+    Vec<Event<RawVoiceEvent, RawFgSoundEvent, RawVideoEvent>>,
+    Vec<Location>,
+) {
+    let mut events = vec![];
+    let mut page_separators = vec![];
+
+    let mut seen_some_marp_page = false;
+
+    // Marp always starts the first page at the beginning of the document,
+    // so a leading separator makes an empty page 1 (which is not shown)
+    let mut marp_page_nth = 1;
+
+    // Position of the current MPageMarker in events
+    let mut marp_page_pos = 0;
+
+    // The pages of an included document are shown until the current page is shown again
+    let mut after_included = false;
+
+    for element in elements {
+        match element {
+            RawDocElement::MarpContentMarker | RawDocElement::MarpHeading(_)
+                if !seen_some_marp_page =>
+            {
+                seen_some_marp_page = true;
+                after_included = false;
+                marp_page_pos = events.len();
+                events.push(Event::MPageMarker {
+                    source,
+                    marp_page_nth,
+                    title: None,
+                });
+            }
+            RawDocElement::MarpContentMarker | RawDocElement::MarpHeading(_) => {
+                // do nothing
+            }
+            RawDocElement::MarpPageMarker(location) => {
+                page_separators.push(location.clone());
+                seen_some_marp_page = true;
+                after_included = false;
+                marp_page_nth += 1;
+                marp_page_pos = events.len();
+                events.push(Event::MPageMarker {
+                    source,
+                    marp_page_nth,
+                    title: None,
+                });
+            }
+            RawDocElement::MVEvent(event) => {
+                if after_included && seen_some_marp_page {
+                    after_included = false;
+
+                    let page = events[marp_page_pos].clone();
+                    marp_page_pos = events.len();
+                    events.push(page);
+                }
+
+                events.push(event.clone());
+            }
+            RawDocElement::Included(included) => {
+                after_included = !included.is_empty();
+                events.extend(included.iter().cloned());
+            }
+        }
+
+        if let RawDocElement::MarpHeading(heading) = element {
+            if let Event::MPageMarker {
+                title: title @ None,
+                ..
+            } = &mut events[marp_page_pos]
+            {
+                *title = Some(heading.clone());
+            }
+        }
+    }
+
+    (events, page_separators)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// Documents which don't include others are not read from the path
+    const MAIN: &str = "/doc/main.md";
+
+    /// Write the documents into a new temporary directory, `main.md` being the one to parse
+    fn write_docs(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new(name);
+
+        for (file, content) in files {
+            dir.write(file, content);
+        }
+
+        dir
+    }
+
+    fn parse_docs(dir: &TempDir) -> Result<DocEvents, ParseError> {
+        let path = dir.path().join("main.md");
+        let env = Environment::for_test(&path);
+
+        DocEvents::parse(&env, &std::fs::read_to_string(&path).unwrap())
+    }
+
+    fn pages(doc: &DocEvents) -> Vec<(usize, usize)> {
+        doc.events
+            .iter()
+            .filter_map(|event| match event {
+                Event::MPageMarker {
+                    source,
+                    marp_page_nth,
+                    ..
+                } => Some((*source, *marp_page_nth)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn try_parse(md: &str) -> Result<DocEvents, ParseError> {
+        DocEvents::parse(&Environment::for_test(Path::new(MAIN)), md)
+    }
+
+    fn parse(md: &str) -> DocEvents {
+        try_parse(md).unwrap()
+    }
+
+    fn separator(line: usize) -> RawDocElement {
+        RawDocElement::MarpPageMarker(Location {
+            path: PathBuf::from("main.md"),
+            line,
+            column: 1,
+        })
+    }
+
+    #[test]
+    fn page_structure_numbers_pages() {
+        let (events, separators) = parse_page_structure(
+            &[
+                RawDocElement::MarpHeading("A".to_string()),
+                separator(3),
+                RawDocElement::MarpContentMarker,
+                separator(5),
+                RawDocElement::MarpHeading("C".to_string()),
+            ],
+            0,
+        );
+
+        let titles: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Event::MPageMarker {
+                    marp_page_nth,
+                    title,
+                    ..
+                } => (*marp_page_nth, title.as_deref()),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(titles, [(1, Some("A")), (2, None), (3, Some("C"))]);
+        assert_eq!(separators.len(), 2);
+    }
+
+    #[test]
+    fn page_structure_leading_separator_skips_page_one() {
+        let (events, _) =
+            parse_page_structure(&[separator(5), RawDocElement::MarpContentMarker], 0);
+
+        assert!(matches!(
+            events[..],
+            [Event::MPageMarker {
+                marp_page_nth: 2,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn heading_divider_numbering() {
+        let doc = parse(
+            "---\nmarp: true\nmarpVideo: true\nheadingDivider: 2\n---\n\n\
+             # A\n\n## B\n\n### not split\n\n---\n\n## C\n",
+        );
+
+        assert_eq!(pages(&doc), [(0, 1), (0, 2), (0, 3), (0, 4)]);
+        assert_eq!(doc.page_separators[0].len(), 3);
+    }
+
+    #[test]
+    fn heading_divider_comment_wins() {
+        let doc = parse(
+            "---\nmarp: true\nmarpVideo: true\nheadingDivider: 2\n---\n\n\
+             # A\n\n## B\n\n<!--\nheadingDivider: 1\n-->\n",
+        );
+
+        assert_eq!(pages(&doc), [(0, 1)]);
+    }
+
+    #[test]
+    fn heading_divider_ignores_leading_include() {
+        let dir = write_docs(
+            "leading-include",
+            &[
+                (
+                    "main.md",
+                    "---\nmarp: true\nmarpVideo: true\nheadingDivider: 1\n---\n\n\
+                     <!--mv include: chapter.md -->\n\n# A\n\n<!--mv speak: a -->\n\n# B\n",
+                ),
+                (
+                    "chapter.md",
+                    "---\nmarp: true\nmarpVideo: true\n---\n\nChapter\n",
+                ),
+            ],
+        );
+
+        let doc = parse_docs(&dir).unwrap();
+
+        assert_eq!(pages(&doc), [(1, 1), (0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn included_pages_are_followed_by_the_current_page() {
+        let dir = write_docs(
+            "after-included",
+            &[
+                (
+                    "main.md",
+                    "---\nmarp: true\nmarpVideo: true\n---\n\n# A\n\n<!--mv speak: a -->\n\n\
+                     <!--mv include: chapter.md -->\n\n<!--mv speak: b -->\n\n---\n\n# B\n",
+                ),
+                (
+                    "chapter.md",
+                    "---\nmarp: true\nmarpVideo: true\n---\n\nChapter\n",
+                ),
+            ],
+        );
+
+        let doc = parse_docs(&dir).unwrap();
+
+        // Page 1 is shown again for `b`, after the included page
+        assert_eq!(pages(&doc), [(0, 1), (1, 1), (0, 1), (0, 2)]);
+
+        let Event::Voice(voice) = &doc.events[4] else {
+            panic!("{:?}", doc.events[4]);
+        };
+
+        assert_eq!(voice.text, "b");
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = write_docs(
+            "include-cycle",
+            &[
+                (
+                    "main.md",
+                    "---\nmarp: true\nmarpVideo: true\n---\n\n# A\n\n<!--mv include: a.md -->\n",
+                ),
+                (
+                    "a.md",
+                    "---\nmarp: true\nmarpVideo: true\n---\n\n# B\n\n<!--mv include: main.md -->\n",
+                ),
+            ],
+        );
+
+        let Err(ParseError::Include { location, source }) = parse_docs(&dir) else {
+            panic!("the cycle must be reported from main.md");
+        };
+
+        let ParseError::IncludeCycle { cycle, .. } = *source else {
+            panic!("{source}");
+        };
+
+        let main = canonical(&dir.path().join("main.md"));
+        let a = canonical(&dir.path().join("a.md"));
+
+        assert_eq!(
+            cycle,
+            format!(
+                "{} -> {} -> {}",
+                main.display(),
+                a.display(),
+                main.display()
+            )
+        );
+        assert_eq!(location.line, 8);
+    }
+
+    #[test]
+    fn include_self() {
+        let dir = write_docs(
+            "include-self",
+            &[(
+                "main.md",
+                "---\nmarp: true\nmarpVideo: true\n---\n\n# A\n\n<!--mv include: main.md -->\n",
+            )],
+        );
+
+        assert!(matches!(
+            parse_docs(&dir),
+            Err(ParseError::IncludeCycle { .. })
+        ));
+    }
+
+    #[test]
+    fn notes_as_speech_skips_empty_comments() {
+        let doc = parse(
+            "---\nmarp: true\nmarpVideo:\n  notesAsSpeech: true\n---\n\n\
+             # A\n\n<!-->\n\n<!--->\n\n<!-- Hello. -->\n",
+        );
+
+        let texts: Vec<_> = doc
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Voice(voice) => Some(voice.text.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(texts, ["Hello."]);
+    }
+
+    #[test]
+    fn marp_video_option_errors_name_the_key() {
+        let error = |options: &str| {
+            let md = format!("---\nmarp: true\nmarpVideo:\n  {options}\n---\n\n# A\n");
+            try_parse(&md).unwrap_err().to_string()
+        };
+
+        let unknown = error("notesAsSpeach: true");
+        assert!(
+            unknown.contains("unknown field `notesAsSpeach`"),
+            "{unknown}"
+        );
+
+        let invalid = error("width: wide");
+        assert!(invalid.contains("invalid type"), "{invalid}");
+    }
+
+    #[test]
+    fn speak_before_page_is_located() {
+        let md = "---\nmarp: true\nmarpVideo: true\n---\n\n<!--mv\nspeak: hi\n-->\n\n# A\n";
+        let Err(ParseError::FirstElementIsNotPage(location)) = try_parse(md) else {
+            panic!("a speak before any page must be an error");
+        };
+
+        assert_eq!(location.line, 7);
+    }
+
+    /// Lines of the diagnostics of the page count check
+    fn page_count_lines(doc: &DocEvents, rendered: usize) -> Vec<usize> {
+        doc.check_page_count(&[rendered])
+            .iter()
+            .map(|d| d.location.line)
+            .collect()
+    }
+
+    #[test]
+    fn page_count_mismatch_points_at_a_separator() {
+        let doc = parse(
+            "---\nmarp: true\nmarpVideo: true\n---\n\n\
+             # A\n\n---\n\n# B\n\n---\n\n# C\n",
+        );
+
+        assert!(doc.check_page_count(&[3]).is_empty());
+
+        // The separator of the first page Marp didn't render
+        assert_eq!(page_count_lines(&doc, 2), [12]);
+        assert_eq!(page_count_lines(&doc, 1), [8]);
+        assert_eq!(page_count_lines(&doc, 0), [1]);
+
+        // The last separator, after which Marp split some page
+        assert_eq!(page_count_lines(&doc, 4), [12]);
+
+        let diagnostic = &doc.check_page_count(&[2])[0];
+        assert_eq!(
+            diagnostic.message,
+            "document has 3 page(s), but Marp rendered 2"
+        );
+    }
+
+    #[test]
+    fn page_count_with_leading_separator() {
+        let doc = parse(
+            "---\nmarp: true\nmarpVideo: true\n---\n\n\
+             ---\n\n# A\n\n---\n\n# B\n",
+        );
+
+        // Page 1 is the empty one before the leading separator
+        assert_eq!(pages(&doc), [(0, 2), (0, 3)]);
+        assert!(doc.check_page_count(&[3]).is_empty());
+        assert_eq!(page_count_lines(&doc, 2), [10]);
+        assert_eq!(page_count_lines(&doc, 4), [10]);
+    }
+}
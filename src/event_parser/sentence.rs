@@ -0,0 +1,93 @@
+const CLOSING: &str = "」』）)\"'";
+
+/// Full-width terminators end a sentence by themselves; ASCII ones need a following space
+fn is_terminator(c: char, next: Option<char>) -> bool {
+    match c {
+        '。' | '！' | '？' => true,
+        '.' | '!' | '?' => next.is_none_or(|next| next.is_whitespace() || CLOSING.contains(next)),
+        _ => false,
+    }
+}
+
+/// Split prose into sentences
+///
+/// Lines are joined with a space, and blank lines always end a sentence.
+pub(super) fn split(text: &str) -> Vec<String> {
+    let paragraphs = text
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .split(|line| line.is_empty())
+        .map(|lines| lines.join(" "))
+        .collect::<Vec<_>>();
+
+    let mut sentences = vec![];
+
+    for paragraph in paragraphs {
+        let mut sentence = String::new();
+        let mut chars = paragraph.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            sentence.push(c);
+
+            if is_terminator(c, chars.peek().copied()) {
+                // Keep closing quotes and brackets with the sentence
+                while let Some(c) = chars.next_if(|c| CLOSING.contains(*c)) {
+                    sentence.push(c);
+                }
+
+                sentences.push(std::mem::take(&mut sentence));
+            }
+        }
+
+        sentences.push(sentence);
+    }
+
+    sentences
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_terminators() {
+        assert_eq!(
+            split("Hello world. How are you? Fine!"),
+            ["Hello world.", "How are you?", "Fine!"]
+        );
+        assert_eq!(
+            split("吾輩は猫である。名前はまだ無い。"),
+            ["吾輩は猫である。", "名前はまだ無い。"]
+        );
+    }
+
+    #[test]
+    fn ascii_terminators_need_a_space() {
+        assert_eq!(
+            split("Version 1.5 is out. See marp.app now."),
+            ["Version 1.5 is out.", "See marp.app now."]
+        );
+    }
+
+    #[test]
+    fn keeps_closing_quotes() {
+        assert_eq!(split("「はい。」と言った。"), ["「はい。」", "と言った。"]);
+        assert_eq!(
+            split(r#"He said "Yes." Then left."#),
+            [r#"He said "Yes.""#, "Then left."]
+        );
+    }
+
+    #[test]
+    fn joins_lines_and_breaks_paragraphs() {
+        assert_eq!(
+            split("\n  first line\n  continues\n\n  next paragraph\n"),
+            ["first line continues", "next paragraph"]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+
+use super::directive::DirectiveError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    /// Unquoted text, trimmed; `\` escapes a delimiter, a quote or itself
+    Bare(String),
+
+    /// `"..."` (with `\"`, `\\`, `\n` and `\t`) or `'...'` (taken literally)
+    Quoted(String),
+
+    /// One of the delimiters, e.g. `,` `=` `:`
+    Delimiter(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Bare(s) => write!(f, "{s}"),
+            Token::Quoted(s) => write!(f, "{s:?}"),
+            Token::Delimiter(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+/// Tokenizer for the values of `mv` directives
+///
+/// Only the given delimiters split the text, so `a:b` is a single bare token in `key=value` lists.
+pub(super) struct Tokenizer<'a> {
+    s: &'a str,
+    pos: usize,
+    delimiters: &'static [char],
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(s: &'a str, delimiters: &'static [char]) -> Self {
+        Self {
+            s,
+            pos: 0,
+            delimiters,
+        }
+    }
+
+    /// The text which is not tokenized yet
+    pub fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> Result<Token, DirectiveError> {
+        let mut value = String::new();
+
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => break,
+                Some('\\') if quote == '"' => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(c) => {
+                        return Err(DirectiveError::new(format!("unknown escape `\\{c}`"))
+                            .with_hint(r#"expected one of: \", \\, \n, \t"#));
+                    }
+                    // unterminated
+                    None => continue,
+                },
+                Some(c) => value.push(c),
+                None => {
+                    return Err(DirectiveError::new(format!(
+                        "unterminated string `{quote}{value}`"
+                    ))
+                    .with_hint(format!("close it with `{quote}`")));
+                }
+            }
+        }
+
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(c) if !self.delimiters.contains(&c) => Err(DirectiveError::new(format!(
+                "unexpected `{}` after `{quote}{value}{quote}`",
+                self.rest().trim()
+            ))
+            .with_hint("quote the whole value")),
+            _ => Ok(Token::Quoted(value)),
+        }
+    }
+
+    fn bare(&mut self) -> Token {
+        let mut value = String::new();
+
+        while let Some(c) = self.peek() {
+            if self.delimiters.contains(&c) {
+                break;
+            }
+
+            self.bump();
+
+            match (c, self.peek()) {
+                ('\\', Some(next))
+                    if self.delimiters.contains(&next) || matches!(next, '\\' | '"' | '\'') =>
+                {
+                    value.push(next);
+                    self.bump();
+                }
+                _ => value.push(c),
+            }
+        }
+
+        Token::Bare(value.trim_end().to_string())
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Result<Token, DirectiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+
+        let c = self.peek()?;
+
+        if self.delimiters.contains(&c) {
+            self.bump();
+            return Some(Ok(Token::Delimiter(c)));
+        }
+
+        match c {
+            '"' | '\'' => {
+                self.bump();
+                Some(self.quoted(c))
+            }
+            _ => Some(Ok(self.bare())),
+        }
+    }
+}
+
+/// Free text such as `speak`; unquoted if the whole text is a quoted string
+pub(super) fn text(s: &str) -> String {
+    let s = s.trim();
+
+    if !s.starts_with(['"', '\'']) {
+        return s.to_string();
+    }
+
+    let tokens: Result<Vec<_>, _> = Tokenizer::new(s, &[]).collect();
+
+    match tokens.as_deref() {
+        Ok([Token::Quoted(text)]) => text.clone(),
+        // e.g. `"Hello," she said`
+        _ => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(s: &str) -> Vec<Token> {
+        Tokenizer::new(s, &[',', '='])
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn error(s: &str) -> String {
+        Tokenizer::new(s, &[',', '='])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err()
+            .message
+    }
+
+    fn bare(s: &str) -> Token {
+        Token::Bare(s.to_string())
+    }
+
+    fn quoted(s: &str) -> Token {
+        Token::Quoted(s.to_string())
+    }
+
+    #[test]
+    fn bare_values_are_trimmed() {
+        assert_eq!(
+            tokens(" path = a b.mp3 , volume=50 "),
+            [
+                bare("path"),
+                Token::Delimiter('='),
+                bare("a b.mp3"),
+                Token::Delimiter(','),
+                bare("volume"),
+                Token::Delimiter('='),
+                bare("50"),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_characters_are_not_delimiters() {
+        assert_eq!(tokens("a:b/c.mp3"), [bare("a:b/c.mp3")]);
+    }
+
+    #[test]
+    fn double_quotes_have_escapes() {
+        assert_eq!(tokens(r#""a,b=\"c\"\\\n\t""#), [quoted("a,b=\"c\"\\\n\t")]);
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(tokens(r"'a,b\n'"), [quoted(r"a,b\n")]);
+    }
+
+    #[test]
+    fn backslash_escapes_in_bare_values() {
+        assert_eq!(tokens(r"a\,b\=c\\d\n"), [bare(r"a,b=c\d\n")]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(r#""abc"#), "unterminated string `\"abc`");
+        assert_eq!(error(r#""a\x""#), r"unknown escape `\x`");
+        assert_eq!(error(r#""a" b"#), "unexpected `b` after `\"a\"`");
+    }
+
+    #[test]
+    fn text_unquotes_whole_quoted_text() {
+        assert_eq!(text(r#"  "Hello, \"world\""  "#), r#"Hello, "world""#);
+        assert_eq!(text("'it''s'"), "'it''s'");
+        assert_eq!(text(r#""Hello," she said"#), r#""Hello," she said"#);
+        assert_eq!(text(" plain: text, as is "), "plain: text, as is");
+    }
+}
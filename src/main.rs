@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod application;
 mod asset_preparator;
@@ -6,51 +6,242 @@ mod environment;
 mod event;
 mod event_parser;
 mod planner;
+mod pronunciation;
+mod subtitle;
+
+#[cfg(test)]
+mod temp_dir;
 
 use environment::Environment;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use event::Event;
 use event_parser::DocEvents;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short, long)]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, required = true)]
+    input: Option<PathBuf>,
 
     #[clap(default_value = "default")]
     profile: String,
+
+    /// Config file applied on top of the user and project ones
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Abort if the document has any diagnostics
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect the config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Check the config, and the voice profiles the document uses
+    Check {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[clap(default_value = "default")]
+        profile: String,
+
+        /// Config file applied on top of the user and project ones
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
+
+/// `config check`; returns whether the config has no problem
+fn check_config(input: &Path, profile: &str, config: Option<&Path>) -> bool {
+    let env = match Environment::try_init(input, profile, config) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return false;
+        }
+    };
+
+    for path in env.config_paths() {
+        println!("config: {}", path.display());
+    }
+
+    let mut problems: Vec<String> = env.check_config().iter().map(ToString::to_string).collect();
+
+    let events = std::fs::read_to_string(env.md_path())
+        .map_err(|e| e.to_string())
+        .and_then(|md| DocEvents::parse(&env, &md).map_err(|e| e.to_string()));
+
+    match events {
+        Ok(events) => {
+            problems.extend(
+                env.check_overrides(&events.overrides)
+                    .iter()
+                    .map(ToString::to_string),
+            );
+
+            let mut voices: Vec<&str> = events
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Voice(voice) => Some(voice.profile.as_str()),
+                    _ => None,
+                })
+                .collect();
+
+            voices.sort();
+            voices.dedup();
+
+            problems.extend(
+                voices
+                    .into_iter()
+                    .filter(|voice| !env.tts_is_profile_exists(voice))
+                    .map(|voice| format!("`tts.{voice}`: used by the document but not configured")),
+            );
+        }
+        Err(e) => problems.push(format!("{}: {e}", env.md_path().display())),
+    }
+
+    for problem in &problems {
+        eprintln!("error: {problem}");
+    }
+
+    problems.is_empty()
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let env = Environment::try_init(&args.input, &args.profile).unwrap();
+    if let Some(Command::Config(ConfigCommand::Check {
+        input,
+        profile,
+        config,
+    })) = &args.command
+    {
+        if !check_config(input, profile, config.as_deref()) {
+            std::process::exit(1);
+        }
+
+        println!("ok");
+        return Ok(());
+    }
+
+    let input = args.input.expect("required unless a subcommand is given");
+
+    let mut env = match Environment::try_init(&input, &args.profile, args.config.as_deref()) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    };
 
     // Create project root dir
     env.init_project_root_dir().unwrap();
 
     // Switch to project root dir
-    std::env::set_current_dir(&env.project_root_dir()).unwrap();
+    std::env::set_current_dir(env.project_root_dir()).unwrap();
 
-    let handle = tokio::spawn({
-        let env = env.clone();
-        async move { application::marp(&env).await }
-    });
+    let md = match std::fs::read_to_string(env.md_path()) {
+        Ok(md) => md,
+        Err(e) => {
+            eprintln!("error: Failed to read {}: {e}", env.md_path().display());
+            std::process::exit(1);
+        }
+    };
 
     // try parse to md
-    let events = DocEvents::parse(&env, &std::fs::read_to_string(&env.md_path()).unwrap()).unwrap();
+    let events = match DocEvents::parse(&env, &md) {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
 
     println!("{events:#?}");
 
-    let (_page_count, events) =
-        tokio::join!(handle, asset_preparator::prepare(&env, &events.events));
+    env.override_profile(&events.overrides);
 
-    println!("{events:#?}");
+    for diagnostic in &events.diagnostics {
+        eprintln!("{diagnostic}\n");
+    }
+
+    if args.strict && !events.diagnostics.is_empty() {
+        eprintln!(
+            "error: aborting due to {} diagnostic(s) (--strict)",
+            events.diagnostics.len()
+        );
+        std::process::exit(1);
+    }
+
+    // Marp renders each source (the document and the included ones) separately
+    let marp = futures::future::join_all(events.sources.iter().enumerate().map(|(k, path)| {
+        let env = &env;
+        async move { application::marp(env, path, &application::marp_output(k)).await }
+    }));
+
+    let (page_counts, prepared) =
+        tokio::join!(marp, asset_preparator::prepare(&env, &events.events));
+
+    let page_counts = match page_counts.into_iter().collect::<anyhow::Result<Vec<_>>>() {
+        Ok(page_counts) => page_counts,
+        Err(e) => {
+            eprintln!("error: marp failed: {e:#}");
+            std::process::exit(1);
+        }
+    };
 
-    let doc = planner::plan(&env, &events.unwrap()).unwrap();
+    let diagnostics = events.check_page_count(&page_counts);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}\n");
+    }
+
+    if !diagnostics.is_empty() {
+        std::process::exit(1);
+    }
+
+    let events = prepared;
+
+    println!("{events:#?}");
 
-    application::encode(&env, &doc).await.unwrap();
+    let events = match events {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    let doc = match planner::plan(&env, &events) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = subtitle::write(&env, &events) {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+
+    if let Err(e) = application::encode(&env, &doc).await {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
 
     Ok(())
 }
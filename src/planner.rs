@@ -1,16 +1,46 @@
 use std::path::Path;
 
+use anyhow::Context;
 use itertools::Itertools;
 
-use crate::asset_preparator::Sound;
-use crate::environment::Environment;
-use crate::event::Event;
+use crate::application::marp_output;
+use crate::asset_preparator::{Clip, Sound};
+use crate::environment::{Caption, CaptionPosition, Ducking, Environment};
+use crate::event::{Bgm, Event, KenBurns, Pan};
+use crate::subtitle;
 
 #[derive(Debug, Clone)]
 pub struct DocumentChannels {
     pub videos: Vec<String>,
     pub fg_sounds: Vec<String>,
     pub bg_sounds: Vec<String>,
+
+    /// Crossfade lengths between `bg_sounds[n]` and `bg_sounds[n + 1]`
+    pub bg_crossfades: Vec<Option<f64>>,
+
+    /// Filters applied to the concatenated video stream
+    pub video_filters: Vec<String>,
+
+    /// Transitions between `videos[n]` and `videos[n + 1]`
+    pub video_transitions: Vec<Option<VideoTransition>>,
+
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoTransition {
+    pub effect: String,
+    pub duration: f64,
+
+    /// Start of the transition on the output timeline
+    pub offset: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: String,
 }
 
 fn ffmpeg_escape(path: &Path) -> String {
@@ -27,30 +57,117 @@ fn ffmpeg_escape(path: &Path) -> String {
         .replace("]", "\\]")
 }
 
-pub fn plan(env: &Environment, events: &[Event<Sound, Sound>]) -> anyhow::Result<DocumentChannels> {
-    let videos = plan_video_stream(env, events);
-    let bg_sounds = plan_bg_audio_stream(env, events);
+/// Loudness normalization gain filter
+fn gain(gain_db: f64) -> String {
+    if gain_db == 0.0 {
+        String::new()
+    } else {
+        format!(",volume={gain_db}dB")
+    }
+}
+
+pub fn plan(
+    env: &Environment,
+    events: &[Event<Sound, Sound, Clip>],
+) -> anyhow::Result<DocumentChannels> {
+    let (videos, video_transitions) = plan_video_stream(env, events);
+    let (bg_sounds, bg_crossfades) = plan_bg_audio_stream(env, events);
     let fg_sounds = plan_fg_audio_stream(env, events);
+    let video_filters = plan_captions(env, events)?;
+    let chapters = plan_chapters(events);
 
     Ok(DocumentChannels {
         videos,
         fg_sounds,
         bg_sounds,
+        bg_crossfades,
+        video_filters,
+        video_transitions,
+        chapters,
     })
 }
 
-fn plan_fg_audio_stream(env: &Environment, events: &[Event<Sound, Sound>]) -> Vec<String> {
+fn plan_captions(
+    env: &Environment,
+    events: &[Event<Sound, Sound, Clip>],
+) -> anyhow::Result<Vec<String>> {
+    let Some(caption) = env.caption() else {
+        return Ok(vec![]);
+    };
+
+    let layout = subtitle::Layout {
+        max_line_chars: caption.max_line_chars,
+        max_lines: caption.max_lines,
+        max_duration: env.subtitle_max_duration(),
+    };
+
+    let mut filters = vec![];
+
+    for (n, cue) in subtitle::cues(layout, events).iter().enumerate() {
+        // drawtext's own escaping is hard to get right; pass the text by file
+        let textfile = format!("caption.{n:04}.txt");
+        std::fs::write(&textfile, cue.lines.join("\n"))
+            .with_context(|| format!("Failed to write {textfile}"))?;
+
+        filters.push(drawtext(env, caption, &textfile, cue));
+    }
+
+    Ok(filters)
+}
+
+/// drawtext filter showing the text of `textfile` while the cue is shown
+fn drawtext(env: &Environment, caption: &Caption, textfile: &str, cue: &subtitle::Cue) -> String {
+    let font = match (&caption.font_file, &caption.font) {
+        (Some(path), _) => format!(":fontfile={}", ffmpeg_escape(path)),
+        (None, Some(font)) => format!(":font={}", ffmpeg_escape(Path::new(font))),
+        (None, None) => String::new(),
+    };
+
+    let font_size = caption.font_size.unwrap_or(env.video_height() / 15);
+    let margin = caption.margin;
+
+    let y = match caption.position {
+        CaptionPosition::Top => format!("{margin}"),
+        CaptionPosition::Center => String::from("(h-text_h)/2"),
+        CaptionPosition::Bottom => format!("h-text_h-{margin}"),
+    };
+
+    format!(
+        "drawtext=textfile={textfile}:expansion=none{font}:fontsize={font_size}:fontcolor={}:box=1:boxcolor={}:boxborderw={}:x=(w-text_w)/2:y={y}:enable=between(t\\,{:.3}\\,{:.3})",
+        caption.font_color,
+        caption.box_color,
+        font_size / 4,
+        cue.start,
+        cue.end,
+    )
+}
+
+fn plan_fg_audio_stream(env: &Environment, events: &[Event<Sound, Sound, Clip>]) -> Vec<String> {
     let mut foreground_sound_stream: Vec<String> = vec![];
 
     for event in events {
         match event {
             Event::Voice(sound) | Event::SoundEffect(sound) => {
                 foreground_sound_stream.push(format!(
-                    "amovie={},volume={}",
+                    "amovie={},volume={}{}",
                     ffmpeg_escape(&env.md_dir().join(&sound.path)),
                     sound.volume / 100.0,
+                    gain(sound.gain_db),
+                ));
+            }
+            Event::VPageMarker(clip) if clip.has_audio => {
+                foreground_sound_stream.push(format!(
+                    "amovie={},atrim=start={}:duration={},asetpts=PTS-STARTPTS,volume={}{}",
+                    ffmpeg_escape(&clip.path),
+                    clip.start,
+                    clip.duration,
+                    clip.volume / 100.0,
+                    gain(clip.gain_db),
                 ));
             }
+            Event::VPageMarker(clip) => {
+                foreground_sound_stream.push(format!("anullsrc,atrim=duration={}", clip.duration));
+            }
             Event::BlankMs(duration) => {
                 let duration = *duration as f32 / 1000.0;
                 foreground_sound_stream.push(format!("anullsrc,atrim=duration={duration}"));
@@ -64,122 +181,380 @@ fn plan_fg_audio_stream(env: &Environment, events: &[Event<Sound, Sound>]) -> Ve
     foreground_sound_stream
 }
 
-fn plan_bg_audio_stream(env: &Environment, events: &[Event<Sound, Sound>]) -> Vec<String> {
+fn plan_bg_audio_stream(
+    env: &Environment,
+    events: &[Event<Sound, Sound, Clip>],
+) -> (Vec<String>, Vec<Option<f64>>) {
     let mut background_sound_stream: Vec<String> = vec![];
 
     let silent_len: f64 = events
         .iter()
         .take_while(|e| !e.is_bgm_event())
-        .filter_map(|e| match e {
-            Event::Voice(s) | Event::SoundEffect(s) => Some(s.duration),
-            Event::BlankMs(millis) => Some(*millis as f64 / 1000.0),
-            _ => None,
-        })
+        .filter_map(|e| e.duration())
         .sum();
 
-    background_sound_stream.push(format!("anullsrc,atrim=duration={silent_len}"));
-
     let bgm_positions: Vec<_> = events.iter().positions(|e| e.is_bgm_event()).collect();
 
-    let bgm_refs: Vec<_> = bgm_positions.iter().map(|i| &events[*i]).collect();
-
     let bgm_durations: Vec<f64> = bgm_positions
         .iter()
         .map(|bgm_pos| {
             let bgm_len: f64 = events[*bgm_pos + 1..]
                 .iter()
                 .take_while(|e| !e.is_bgm_event())
-                .filter_map(|e| match e {
-                    Event::Voice(s) | Event::SoundEffect(s) => Some(s.duration),
-                    Event::BlankMs(millis) => Some(*millis as f64 / 1000.0),
-                    _ => None,
-                })
+                .filter_map(|e| e.duration())
                 .sum();
 
             bgm_len
         })
         .collect();
 
-    for (bref, bdur) in bgm_refs.iter().zip(bgm_durations.iter()) {
-        match bref {
-            Event::MVBGMMarker { path, volume } => match path {
-                Some(path) => {
-                    background_sound_stream.push(format!(
-                        "amovie={},volume={},aloop=-1:2147483647,atrim=duration={bdur}",
-                        ffmpeg_escape(&env.md_dir().join(&path.to_str().unwrap())),
-                        *volume / 100.0,
-                    ));
+    // Crossfade into each BGM. Like the video transitions, the previous section is extended by the
+    // crossfade length to keep the timeline.
+    let crossfades: Vec<Option<f64>> = bgm_positions
+        .iter()
+        .zip(bgm_durations.iter())
+        .map(|(bgm_pos, bdur)| {
+            let Event::MVBGMMarker(bgm) = &events[*bgm_pos] else {
+                return None;
+            };
+
+            let crossfade = bgm.crossfade_ms.unwrap_or(env.bgm_crossfade_ms()) as f64 / 1000.0;
+            let crossfade = crossfade.min(*bdur);
+
+            (crossfade > 0.0).then_some(crossfade)
+        })
+        .collect();
+
+    let extension = |n: usize| crossfades.get(n).copied().flatten().unwrap_or(0.0);
+
+    background_sound_stream.push(format!(
+        "anullsrc,atrim=duration={}",
+        silent_len + extension(0),
+    ));
+
+    for (n, (bgm_pos, bdur)) in bgm_positions.iter().zip(bgm_durations.iter()).enumerate() {
+        let Event::MVBGMMarker(bgm) = &events[*bgm_pos] else {
+            continue;
+        };
+
+        let bdur = bdur + extension(n + 1);
+
+        match &bgm.path {
+            Some(path) => {
+                let fade_in = bgm.fade_in_ms.unwrap_or(env.bgm_fade_in_ms()) as f64 / 1000.0;
+                let fade_out = bgm.fade_out_ms.unwrap_or(env.bgm_fade_out_ms()) as f64 / 1000.0;
+
+                let mut fades = String::new();
+
+                if fade_in > 0.0 {
+                    fades.push_str(&format!(",afade=t=in:d={}", fade_in.min(bdur)));
                 }
-                None => {
-                    background_sound_stream.push(format!(
-                        "anullsrc,volume={},atrim=duration={bdur}",
-                        *volume / 100.0,
-                    ));
+
+                if fade_out > 0.0 {
+                    let fade_out = fade_out.min(bdur);
+                    fades.push_str(&format!(",afade=t=out:st={}:d={fade_out}", bdur - fade_out));
                 }
-            },
-            _ => {}
+
+                background_sound_stream.push(format!(
+                    "amovie={},volume={}{},aloop=-1:2147483647,atrim=duration={bdur}{}{fades}",
+                    ffmpeg_escape(&env.md_dir().join(path.to_str().unwrap())),
+                    bgm.volume / 100.0,
+                    gain(bgm.gain_db),
+                    plan_ducking(env, bgm, &events[*bgm_pos + 1..]),
+                ));
+            }
+            None => {
+                background_sound_stream.push(format!(
+                    "anullsrc,volume={},atrim=duration={bdur}",
+                    bgm.volume / 100.0,
+                ));
+            }
         }
     }
 
     println!("{background_sound_stream:#?}");
 
-    background_sound_stream
+    (background_sound_stream, crossfades)
 }
 
-fn plan_video_stream(env: &Environment, events: &[Event<Sound, Sound>]) -> Vec<String> {
-    let mut video_stream: Vec<String> = vec![];
+fn zoompan(env: &Environment, ken_burns: &KenBurns, frames: usize) -> String {
+    let KenBurns {
+        zoom_from,
+        zoom_to,
+        pan,
+    } = ken_burns;
+
+    // progress: 0 -> 1 over the page
+    let progress = format!("on/{frames}");
+
+    let (x, y) = match pan {
+        Pan::Center => (
+            String::from("(iw-iw/zoom)/2"),
+            String::from("(ih-ih/zoom)/2"),
+        ),
+        Pan::Left => (
+            format!("(iw-iw/zoom)*(1-{progress})"),
+            String::from("(ih-ih/zoom)/2"),
+        ),
+        Pan::Right => (
+            format!("(iw-iw/zoom)*{progress}"),
+            String::from("(ih-ih/zoom)/2"),
+        ),
+        Pan::Up => (
+            String::from("(iw-iw/zoom)/2"),
+            format!("(ih-ih/zoom)*(1-{progress})"),
+        ),
+        Pan::Down => (
+            String::from("(iw-iw/zoom)/2"),
+            format!("(ih-ih/zoom)*{progress}"),
+        ),
+    };
+
+    format!(
+        "zoompan=z={zoom_from}+({zoom_to}-{zoom_from})*{progress}:x={x}:y={y}:d={frames}:s={}x{}:fps={}",
+        env.video_width(),
+        env.video_height(),
+        env.video_framerate(),
+    )
+}
+
+/// Volume envelope which dips the BGM while voices in its section are played
+fn plan_ducking(env: &Environment, bgm: &Bgm, events: &[Event<Sound, Sound, Clip>]) -> String {
+    let default = Ducking::default();
+    let ducking = env.ducking();
+
+    let Some(level) = bgm.duck.or(ducking.map(|d| d.level)) else {
+        return String::new();
+    };
+
+    if level >= 100.0 {
+        return String::new();
+    }
+
+    let ducking = ducking.unwrap_or(&default);
+    let attack = ducking.attack_ms.max(1) as f64 / 1000.0;
+    let release = ducking.release_ms.max(1) as f64 / 1000.0;
+
+    // Voice intervals relative to the section start. Close ones are merged so the music doesn't
+    // pump between sentences.
+    let mut intervals: Vec<(f64, f64)> = vec![];
+    let mut t = 0.0;
+
+    for event in events.iter().take_while(|e| !e.is_bgm_event()) {
+        let duration = event.duration().unwrap_or(0.0);
+
+        if let Event::Voice(_) = event {
+            match intervals.last_mut() {
+                Some((_, end)) if t - *end < attack + release => *end = t + duration,
+                _ => intervals.push((t, t + duration)),
+            }
+        }
+
+        t += duration;
+    }
+
+    if intervals.is_empty() {
+        return String::new();
+    }
+
+    // 0 -> 1 ramp over `attack` before the voice, 1 -> 0 over `release` after it
+    let ducked = intervals
+        .iter()
+        .map(|(start, end)| {
+            format!(
+                "clip((t-({}))/{attack},0,1)*clip((({})-t)/{release},0,1)",
+                start - attack,
+                end + release,
+            )
+        })
+        .join("+");
 
-    let page_positions: Vec<_> = events.iter().positions(|e| e.is_page()).collect();
+    let expr = format!("1-{}*min(1,{ducked})", 1.0 - level as f64 / 100.0);
+
+    format!(
+        ",asetpts=PTS-STARTPTS,volume=volume={}:eval=frame",
+        expr.replace(',', "\\,")
+    )
+}
 
-    let page_refs: Vec<_> = page_positions.iter().map(|i| &events[*i]).collect();
+/// Slides shorter than this are dropped from the video stream
+const MIN_PAGE_DURATION: f64 = 60.0 * 2.0 / 1000.0;
 
-    let page_durations: Vec<f64> = page_positions
+/// Positions of the page markers and the duration of each page
+fn pages(events: &[Event<Sound, Sound, Clip>]) -> Vec<(usize, f64)> {
+    events
         .iter()
+        .positions(|e| e.is_page())
         .map(|page_pos| {
-            let page_len: f64 = events[*page_pos + 1..]
+            // Video clip pages have their own length
+            let own_len = events[page_pos].duration().unwrap_or(0.0);
+
+            let page_len: f64 = events[page_pos + 1..]
                 .iter()
                 .take_while(|e| !e.is_page())
-                .filter_map(|e| match e {
-                    Event::Voice(s) | Event::SoundEffect(s) => Some(s.duration),
-                    Event::BlankMs(millis) => Some(*millis as f64 / 1000.0),
-                    _ => None,
-                })
+                .filter_map(|e| e.duration())
                 .sum();
 
-            page_len
+            (page_pos, own_len + page_len)
         })
-        .collect();
+        .collect()
+}
 
-    println!("{page_durations:#?}");
+fn plan_chapters(events: &[Event<Sound, Sound, Clip>]) -> Vec<Chapter> {
+    let mut chapters = vec![];
+    let mut t = 0.0;
 
-    for (pref, pdur) in page_refs.iter().zip(page_durations.iter()) {
-        // Drop slides less than one frame
-        if *pdur < 60.0 * 2.0 / 1000.0 {
-            println!("WARN: Skip frame");
+    for (n, (page_pos, pdur)) in pages(events).into_iter().enumerate() {
+        if pdur < MIN_PAGE_DURATION {
             continue;
         }
 
+        let explicit_title = events[page_pos + 1..]
+            .iter()
+            .take_while(|e| !e.is_page())
+            .find_map(|e| match e {
+                Event::ChapterMarker { title } => Some(title.clone()),
+                _ => None,
+            });
+
+        let page_title = match &events[page_pos] {
+            Event::MPageMarker { title, .. } => title.clone(),
+            _ => None,
+        };
+
+        chapters.push(Chapter {
+            start: t,
+            end: t + pdur,
+            title: explicit_title
+                .or(page_title)
+                .unwrap_or_else(|| format!("Page {}", n + 1)),
+        });
+
+        t += pdur;
+    }
+
+    chapters
+}
+
+fn plan_video_stream(
+    env: &Environment,
+    events: &[Event<Sound, Sound, Clip>],
+) -> (Vec<String>, Vec<Option<VideoTransition>>) {
+    let mut video_stream: Vec<String> = vec![];
+
+    let pages: Vec<_> = pages(events)
+        .into_iter()
+        .filter(|(_, pdur)| {
+            // Drop slides less than one frame
+            if *pdur < MIN_PAGE_DURATION {
+                println!("WARN: Skip frame");
+            }
+
+            *pdur >= MIN_PAGE_DURATION
+        })
+        .collect();
+
+    // The outgoing page is extended by the transition length, so the incoming page still starts
+    // at its original position and the audio stays in sync.
+    let mut transitions = vec![];
+    let mut offset = 0.0;
+
+    for (n, (page_pos, pdur)) in pages.iter().enumerate() {
+        offset += pdur;
+
+        let Some((_, next_pdur)) = pages.get(n + 1) else {
+            break;
+        };
+
+        let transition = events[page_pos + 1..]
+            .iter()
+            .take_while(|e| !e.is_page())
+            .filter_map(|e| match e {
+                Event::TransitionMarker { transition } => Some(transition.as_ref()),
+                _ => None,
+            })
+            .last()
+            .unwrap_or(env.default_transition());
+
+        transitions.push(transition.map(|t| VideoTransition {
+            effect: t.effect.clone(),
+            duration: (t.duration_ms as f64 / 1000.0).min(*next_pdur),
+            offset,
+        }));
+    }
+
+    // xfade requires the same framerate and timebase on both inputs
+    let normalize = if transitions.iter().any(Option::is_some) {
+        format!(",fps={},settb=AVTB", env.video_framerate())
+    } else {
+        String::new()
+    };
+
+    for (n, (page_pos, pdur)) in pages.iter().enumerate() {
+        let pref = &events[*page_pos];
+
+        let pdur = pdur
+            + transitions
+                .get(n)
+                .and_then(Option::as_ref)
+                .map_or(0.0, |t| t.duration);
+
         match pref {
-            Event::MPageMarker { marp_page_nth } => {
+            Event::MPageMarker {
+                source,
+                marp_page_nth,
+                ..
+            } => {
                 video_stream.push(format!(
-                    "movie=./marp_doc.{marp_page_nth:03},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}",
+                    "movie=./{}.{marp_page_nth:03},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}{normalize}",
+                    marp_output(*source),
                     env.video_width(),
                     env.video_height(),
                 ));
             }
-            Event::IPageMarker { path } => {
+            Event::IPageMarker {
+                path,
+                ken_burns: Some(ken_burns),
+            } => {
+                let frames = (pdur * env.video_framerate() as f64).ceil() as usize;
+
                 video_stream.push(format!(
-                    "movie={},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}",
-                    ffmpeg_escape(&env.md_dir().join(&path.to_str().unwrap())),
+                    "movie={},scale={}:{},setsar=1:1,{},trim=duration={pdur}{normalize}",
+                    ffmpeg_escape(&env.md_dir().join(path.to_str().unwrap())),
+                    // Upscale before zoompan to reduce jitter
+                    env.video_width() * 2,
+                    env.video_height() * 2,
+                    zoompan(env, ken_burns, frames),
+                ));
+            }
+            Event::IPageMarker {
+                path,
+                ken_burns: None,
+            } => {
+                video_stream.push(format!(
+                    "movie={},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}{normalize}",
+                    ffmpeg_escape(&env.md_dir().join(path.to_str().unwrap())),
                     env.video_width(),
                     env.video_height(),
                 ));
             }
             Event::CPageMarker { color } => {
                 video_stream.push(format!(
-                    "color=c={color},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}",
+                    "color=c={color},scale={}:{},setsar=1:1,loop=-1:1,trim=duration={pdur}{normalize}",
+                    env.video_width(),
+                    env.video_height(),
+                ));
+            }
+            Event::VPageMarker(clip) => {
+                // Hold the last frame while the rest of the page is spoken
+                video_stream.push(format!(
+                    "movie={},trim=start={}:duration={},setpts=PTS-STARTPTS,scale={}:{},setsar=1:1,tpad=stop_mode=clone:stop_duration={},trim=duration={pdur}{normalize}",
+                    ffmpeg_escape(&clip.path),
+                    clip.start,
+                    clip.duration,
                     env.video_width(),
                     env.video_height(),
+                    (pdur - clip.duration).max(0.0),
                 ));
             }
             _ => {}
@@ -188,5 +563,398 @@ fn plan_video_stream(env: &Environment, events: &[Event<Sound, Sound>]) -> Vec<S
 
     println!("{video_stream:#?}");
 
-    video_stream
+    (video_stream, transitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::event::Transition;
+
+    fn env(config: &str) -> Environment {
+        Environment::for_test_config(Path::new("/doc/main.md"), config)
+    }
+
+    fn voice(duration: f64) -> Event<Sound, Sound, Clip> {
+        Event::Voice(Sound {
+            duration,
+            path: PathBuf::from("voice.wav"),
+            volume: 100.0,
+            text: Some(String::from("text")),
+            gain_db: 0.0,
+        })
+    }
+
+    fn clip(duration: f64, has_audio: bool) -> Event<Sound, Sound, Clip> {
+        Event::VPageMarker(Clip {
+            duration,
+            path: PathBuf::from("/doc/clip.mp4"),
+            volume: 50.0,
+            start: 2.0,
+            has_audio,
+            gain_db: 0.0,
+        })
+    }
+
+    fn page(marp_page_nth: usize, title: Option<&str>) -> Event<Sound, Sound, Clip> {
+        Event::MPageMarker {
+            source: 0,
+            marp_page_nth,
+            title: title.map(String::from),
+        }
+    }
+
+    #[test]
+    fn drawtext_places_the_caption() {
+        let config = "[profile.default]\nheight = 720\n\
+                      [profile.default.caption]\nfont = \"Noto Sans\"\nposition = \"top\"\nmargin = 20\n";
+        let env = env(config);
+
+        let cue = subtitle::Cue {
+            start: 1.0,
+            end: 2.5,
+            lines: vec![String::from("Hello")],
+        };
+
+        assert_eq!(
+            drawtext(&env, env.caption().unwrap(), "caption.0000.txt", &cue),
+            "drawtext=textfile=caption.0000.txt:expansion=none:font=Noto Sans:fontsize=48:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=12:x=(w-text_w)/2:y=20:enable=between(t\\,1.000\\,2.500)"
+        );
+    }
+
+    #[test]
+    fn drawtext_defaults_to_the_bottom() {
+        let env = env("[profile.default.caption]\nfont_size = 30\n");
+
+        let cue = subtitle::Cue {
+            start: 0.0,
+            end: 1.0,
+            lines: vec![],
+        };
+
+        let filter = drawtext(&env, env.caption().unwrap(), "caption.0000.txt", &cue);
+
+        assert!(filter.contains(":fontsize=30:"), "{filter}");
+        assert!(filter.contains(":y=h-text_h-40:"), "{filter}");
+        assert!(!filter.contains(":font="), "{filter}");
+    }
+
+    #[test]
+    fn chapter_titles() {
+        let events = [
+            page(1, Some("Intro")),
+            voice(2.0),
+            page(2, Some("Heading")),
+            Event::ChapterMarker {
+                title: String::from("Explicit"),
+            },
+            voice(1.0),
+            // Too short to be shown
+            page(3, Some("Skipped")),
+            page(4, None),
+            voice(0.5),
+        ];
+
+        let chapters: Vec<_> = plan_chapters(&events)
+            .into_iter()
+            .map(|c| (c.start, c.end, c.title))
+            .collect();
+
+        assert_eq!(
+            chapters,
+            [
+                (0.0, 2.0, String::from("Intro")),
+                (2.0, 3.0, String::from("Explicit")),
+                (3.0, 3.5, String::from("Page 4")),
+            ]
+        );
+    }
+
+    #[test]
+    fn clip_page_holds_the_last_frame() {
+        let env = env("[profile.default]\nwidth = 1280\nheight = 720\n");
+        let events = [page(1, None), voice(1.0), clip(3.0, false), voice(2.0)];
+
+        let (videos, _) = plan_video_stream(&env, &events);
+
+        assert_eq!(
+            videos[1],
+            "movie=/doc/clip.mp4,trim=start=2:duration=3,setpts=PTS-STARTPTS,scale=1280:720,setsar=1:1,tpad=stop_mode=clone:stop_duration=2,trim=duration=5"
+        );
+
+        // The page is spoken after the clip
+        assert_eq!(
+            plan_fg_audio_stream(&env, &events)[1..],
+            [
+                "anullsrc,atrim=duration=3",
+                "amovie=/doc/voice.wav,volume=1"
+            ]
+        );
+    }
+
+    #[test]
+    fn clip_audio() {
+        let env = env("");
+
+        assert_eq!(
+            plan_fg_audio_stream(&env, &[clip(3.0, true)]),
+            ["amovie=/doc/clip.mp4,atrim=start=2:duration=3,asetpts=PTS-STARTPTS,volume=0.5"]
+        );
+    }
+
+    fn transition(effect: Option<&str>) -> Event<Sound, Sound, Clip> {
+        Event::TransitionMarker {
+            transition: effect.map(|effect| Transition {
+                effect: String::from(effect),
+                duration_ms: 1000,
+            }),
+        }
+    }
+
+    fn transitions(
+        env: &Environment,
+        events: &[Event<Sound, Sound, Clip>],
+    ) -> Vec<Option<(String, f64, f64)>> {
+        plan_video_stream(env, events)
+            .1
+            .into_iter()
+            .map(|t| t.map(|t| (t.effect, t.duration, t.offset)))
+            .collect()
+    }
+
+    #[test]
+    fn transition_offsets_keep_the_timeline() {
+        let env = env("[profile.default]\nframerate = 25\n");
+
+        let events = [
+            page(1, None),
+            voice(3.0),
+            transition(Some("fade")),
+            page(2, None),
+            voice(2.0),
+            transition(None),
+            page(3, None),
+            voice(2.0),
+            transition(Some("wipeleft")),
+            page(4, None),
+            voice(0.5),
+        ];
+
+        // Offsets are where the next page starts; the last one is clamped to the next page
+        assert_eq!(
+            transitions(&env, &events),
+            [
+                Some((String::from("fade"), 1.0, 3.0)),
+                None,
+                Some((String::from("wipeleft"), 0.5, 7.0)),
+            ]
+        );
+
+        // The outgoing pages are extended by the transitions
+        let (videos, _) = plan_video_stream(&env, &events);
+        let durations: Vec<_> = videos
+            .iter()
+            .map(|v| v.split("trim=duration=").nth(1).unwrap())
+            .collect();
+
+        assert_eq!(
+            durations,
+            [
+                "4,fps=25,settb=AVTB",
+                "2,fps=25,settb=AVTB",
+                "2.5,fps=25,settb=AVTB",
+                "0.5,fps=25,settb=AVTB",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_transition() {
+        let env = env("[profile.default.transition]\neffect = \"fade\"\nduration_ms = 250\n");
+
+        let events = [
+            page(1, None),
+            voice(1.0),
+            page(2, None),
+            voice(1.0),
+            transition(None),
+            page(3, None),
+            voice(1.0),
+        ];
+
+        assert_eq!(
+            transitions(&env, &events),
+            [Some((String::from("fade"), 0.25, 1.0)), None]
+        );
+    }
+
+    #[test]
+    fn hard_cuts_only() {
+        let (videos, transitions) = plan_video_stream(
+            &env(""),
+            &[page(1, None), voice(1.0), page(2, None), voice(1.0)],
+        );
+
+        assert!(transitions.iter().all(Option::is_none));
+        assert!(
+            videos.iter().all(|v| v.ends_with("trim=duration=1")),
+            "{videos:?}"
+        );
+    }
+
+    #[test]
+    fn zoompan_pans_over_the_page() {
+        let env = env("[profile.default]\nwidth = 1280\nheight = 720\nframerate = 25\n");
+
+        let ken_burns = KenBurns {
+            zoom_from: 1.0,
+            zoom_to: 1.5,
+            pan: Pan::Left,
+        };
+
+        assert_eq!(
+            zoompan(&env, &ken_burns, 100),
+            "zoompan=z=1+(1.5-1)*on/100:x=(iw-iw/zoom)*(1-on/100):y=(ih-ih/zoom)/2:d=100:s=1280x720:fps=25"
+        );
+
+        let events = [
+            Event::IPageMarker {
+                path: PathBuf::from("bg.png"),
+                ken_burns: Some(ken_burns),
+            },
+            voice(1.5),
+        ];
+
+        let (videos, _) = plan_video_stream(&env, &events);
+
+        // Upscaled, and 37.5 frames rounded up
+        assert!(
+            videos[0].starts_with(
+                "movie=/doc/bg.png,scale=2560:1440,setsar=1:1,zoompan=z=1+(1.5-1)*on/38:"
+            ),
+            "{}",
+            videos[0]
+        );
+        assert!(
+            videos[0].ends_with(":d=38:s=1280x720:fps=25,trim=duration=1.5"),
+            "{}",
+            videos[0]
+        );
+    }
+
+    fn bgm(path: Option<&str>) -> Bgm {
+        Bgm {
+            path: path.map(PathBuf::from),
+            volume: 100.0,
+            duck: None,
+            fade_in_ms: None,
+            fade_out_ms: None,
+            crossfade_ms: None,
+            gain_db: 0.0,
+        }
+    }
+
+    const DUCKING: &str =
+        "[profile.default.ducking]\nlevel = 20.0\nattack_ms = 250\nrelease_ms = 500\n";
+
+    #[test]
+    fn ducking_merges_close_voices() {
+        let env = env(DUCKING);
+        let events = [voice(1.0), Event::BlankMs(500), voice(1.0)];
+
+        assert_eq!(
+            plan_ducking(&env, &bgm(Some("bgm.mp3")), &events),
+            ",asetpts=PTS-STARTPTS,volume=volume=1-0.8*min(1\\,clip((t-(-0.25))/0.25\\,0\\,1)*clip(((3)-t)/0.5\\,0\\,1)):eval=frame"
+        );
+    }
+
+    #[test]
+    fn ducking_separates_distant_voices() {
+        let env = env(DUCKING);
+        let events = [voice(1.0), Event::BlankMs(1000), voice(1.0)];
+
+        assert_eq!(
+            plan_ducking(&env, &bgm(Some("bgm.mp3")), &events),
+            ",asetpts=PTS-STARTPTS,volume=volume=1-0.8*min(1\\,\
+             clip((t-(-0.25))/0.25\\,0\\,1)*clip(((1.5)-t)/0.5\\,0\\,1)+\
+             clip((t-(1.75))/0.25\\,0\\,1)*clip(((3.5)-t)/0.5\\,0\\,1)):eval=frame"
+        );
+    }
+
+    #[test]
+    fn ducking_disabled() {
+        let events = [voice(1.0)];
+
+        let no_duck = Bgm {
+            duck: Some(100.0),
+            ..bgm(Some("bgm.mp3"))
+        };
+
+        assert_eq!(plan_ducking(&env(DUCKING), &no_duck, &events), "");
+        assert_eq!(plan_ducking(&env(""), &bgm(Some("bgm.mp3")), &events), "");
+
+        // `duck=` without the profile's ducking
+        let duck = Bgm {
+            duck: Some(50.0),
+            ..bgm(Some("bgm.mp3"))
+        };
+
+        assert!(plan_ducking(&env(""), &duck, &events).contains("volume=volume=1-0.5*"));
+    }
+
+    #[test]
+    fn bgm_crossfade_extends_the_previous_section() {
+        let events = [
+            page(1, None),
+            Event::MVBGMMarker(Bgm {
+                fade_out_ms: Some(1000),
+                ..bgm(Some("a.mp3"))
+            }),
+            voice(4.0),
+            Event::MVBGMMarker(Bgm {
+                crossfade_ms: Some(2000),
+                ..bgm(Some("b.mp3"))
+            }),
+            voice(3.0),
+        ];
+
+        let (bg_sounds, crossfades) = plan_bg_audio_stream(&env(""), &events);
+
+        // `crossfades[n]` joins `bg_sounds[n]` and `bg_sounds[n + 1]`; the first one is silence
+        assert_eq!(crossfades, [None, Some(2.0)]);
+        assert_eq!(
+            bg_sounds,
+            [
+                "anullsrc,atrim=duration=0",
+                "amovie=/doc/a.mp3,volume=1,aloop=-1:2147483647,atrim=duration=6,afade=t=out:st=5:d=1",
+                "amovie=/doc/b.mp3,volume=1,aloop=-1:2147483647,atrim=duration=3",
+            ]
+        );
+    }
+
+    #[test]
+    fn bgm_crossfade_is_clamped_to_the_section() {
+        let events = [
+            page(1, None),
+            voice(1.0),
+            Event::MVBGMMarker(Bgm {
+                crossfade_ms: Some(5000),
+                ..bgm(None)
+            }),
+            voice(3.0),
+        ];
+
+        let (bg_sounds, crossfades) = plan_bg_audio_stream(&env(""), &events);
+
+        assert_eq!(crossfades, [Some(3.0)]);
+        assert_eq!(
+            bg_sounds,
+            [
+                "anullsrc,atrim=duration=4",
+                "anullsrc,volume=1,atrim=duration=3"
+            ]
+        );
+    }
 }
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use regex::{NoExpand, Regex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Invalid pronunciation regex `{key}`: {source}")]
+pub struct PronunciationError {
+    pub key: String,
+    pub source: regex::Error,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    key: String,
+    regex: Regex,
+    replacement: String,
+
+    /// `$1` etc. in the replacement refer to the groups (regex keys only)
+    expand: bool,
+}
+
+/// Replacements applied to the text before TTS
+///
+/// `"/.../"` keys are regular expressions, the others are literals.
+/// Longer keys are applied first, so `"Marp Video"` wins over `"Marp"`.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    entries: Vec<Entry>,
+}
+
+impl Dictionary {
+    /// Add the entries, overriding the same keys; invalid regexes are skipped and returned
+    pub fn merge(&mut self, map: &BTreeMap<String, String>) -> Vec<PronunciationError> {
+        let mut errors = vec![];
+
+        for (key, replacement) in map {
+            let (regex, expand) = match key.strip_prefix('/').and_then(|k| k.strip_suffix('/')) {
+                Some(pattern) if !pattern.is_empty() => (Regex::new(pattern), true),
+                _ => (Regex::new(&regex::escape(key)), false),
+            };
+
+            let regex = match regex {
+                Ok(regex) => regex,
+                Err(source) => {
+                    errors.push(PronunciationError {
+                        key: key.clone(),
+                        source,
+                    });
+                    continue;
+                }
+            };
+
+            self.entries.retain(|e| e.key != *key);
+            self.entries.push(Entry {
+                key: key.clone(),
+                regex,
+                replacement: replacement.clone(),
+                expand,
+            });
+        }
+
+        self.entries.sort_by(|a, b| {
+            b.key
+                .len()
+                .cmp(&a.key.len())
+                .then_with(|| a.key.cmp(&b.key))
+        });
+
+        errors
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        self.entries.iter().fold(text.to_string(), |text, entry| {
+            if entry.expand {
+                entry.regex.replace_all(&text, entry.replacement.as_str())
+            } else {
+                entry.regex.replace_all(&text, NoExpand(&entry.replacement))
+            }
+            .into_owned()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::environment::Environment;
+
+    fn dictionary(entries: &[(&str, &str)]) -> Dictionary {
+        let mut dictionary = Dictionary::default();
+        let errors = dictionary.merge(&map(entries));
+        assert!(errors.is_empty(), "{errors:?}");
+        dictionary
+    }
+
+    fn map(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn longer_keys_first() {
+        let dictionary = dictionary(&[("Marp", "marp"), ("Marp Video", "marp video")]);
+        assert_eq!(
+            dictionary.apply("Marp Video uses Marp"),
+            "marp video uses marp"
+        );
+    }
+
+    #[test]
+    fn regex_expands_groups() {
+        let dictionary = dictionary(&[(r"/v(\d+)\.(\d+)/", "version $1 point $2")]);
+        assert_eq!(
+            dictionary.apply("v1.23 and v2.0"),
+            "version 1 point 23 and version 2 point 0"
+        );
+    }
+
+    #[test]
+    fn literal_does_not_expand() {
+        let dictionary = dictionary(&[("USD", "$1 dollars"), ("a.b", "dot")]);
+        assert_eq!(dictionary.apply("10 USD"), "10 $1 dollars");
+
+        // `.` is not a regex in a literal key
+        assert_eq!(dictionary.apply("a.b axb"), "dot axb");
+    }
+
+    #[test]
+    fn invalid_regex_is_skipped() {
+        let mut dictionary = Dictionary::default();
+        let errors = dictionary.merge(&map(&[("/(/", "x"), ("a", "b")]));
+
+        assert_eq!(
+            errors.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(),
+            ["/(/"]
+        );
+        assert_eq!(dictionary.apply("(a"), "(b");
+    }
+
+    #[test]
+    fn later_dictionaries_override_the_same_key() {
+        let env = Environment::for_test_config(
+            Path::new("/doc/main.md"),
+            "[pronunciation]\nGUI = \"global\"\nCLI = \"global\"\nTTS = \"global\"\n\
+             [tts.narrator.pronunciation]\nCLI = \"narrator\"\nTTS = \"narrator\"\n",
+        );
+
+        // As the event parser does with the frontmatter
+        let mut dictionary = env.pronunciation("narrator");
+        dictionary.merge(&map(&[("TTS", "frontmatter")]));
+
+        assert_eq!(
+            dictionary.apply("GUI CLI TTS"),
+            "global narrator frontmatter"
+        );
+        assert_eq!(
+            env.pronunciation("other").apply("GUI CLI TTS"),
+            "global global global"
+        );
+    }
+}
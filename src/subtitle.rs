@@ -0,0 +1,290 @@
+use anyhow::Context;
+
+use crate::asset_preparator::{Clip, Sound};
+use crate::environment::Environment;
+use crate::event::Event;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub max_line_chars: usize,
+    pub max_lines: usize,
+    pub max_duration: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub lines: Vec<String>,
+}
+
+/// Wrap text into lines of at most `max_chars` characters.
+///
+/// Breaks on whitespace where possible, otherwise (e.g. CJK text) on character boundaries.
+pub fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        while !word.is_empty() {
+            let line_len = line.chars().count();
+            let sep = if line.is_empty() { 0 } else { 1 };
+
+            if line_len + sep + word.len() <= max_chars {
+                if sep == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.drain(..));
+                break;
+            }
+
+            if word.len() <= max_chars {
+                // The word fits on a fresh line
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+
+            // The word is longer than a line; fill the current line up with it
+            if line_len + sep >= max_chars {
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+
+            let room = max_chars - line_len - sep;
+
+            if sep == 1 {
+                line.push(' ');
+            }
+            line.extend(word.drain(..room));
+            lines.push(std::mem::take(&mut line));
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Split a single spoken line into one or more cues.
+fn split_cue(layout: Layout, start: f64, duration: f64, text: &str) -> Vec<Cue> {
+    let lines = wrap(text, layout.max_line_chars);
+
+    if lines.is_empty() {
+        return vec![];
+    }
+
+    let needed = (duration / layout.max_duration).ceil().max(1.0) as usize;
+    let per_cue = lines
+        .len()
+        .div_ceil(needed)
+        .clamp(1, layout.max_lines.max(1));
+
+    let chunks: Vec<&[String]> = lines.chunks(per_cue).collect();
+
+    // Share the sound duration between chunks by their character count
+    let total_chars: usize = lines.iter().map(|l| l.chars().count()).sum();
+
+    let mut cues = vec![];
+    let mut t = start;
+
+    for chunk in chunks {
+        let chars: usize = chunk.iter().map(|l| l.chars().count()).sum();
+        let len = duration * chars as f64 / total_chars.max(1) as f64;
+
+        cues.push(Cue {
+            start: t,
+            end: t + len,
+            lines: chunk.to_vec(),
+        });
+
+        t += len;
+    }
+
+    cues
+}
+
+pub fn cues(layout: Layout, events: &[Event<Sound, Sound, Clip>]) -> Vec<Cue> {
+    let mut cues = vec![];
+    let mut t = 0.0;
+
+    for event in events {
+        if let Event::Voice(Sound {
+            text: Some(text),
+            duration,
+            ..
+        }) = event
+        {
+            cues.extend(split_cue(layout, t, *duration, text));
+        }
+
+        t += event.duration().unwrap_or(0.0);
+    }
+
+    cues
+}
+
+fn timestamp(t: f64, fraction_sep: char) -> String {
+    let millis = (t * 1000.0).round() as u64;
+
+    format!(
+        "{:02}:{:02}:{:02}{fraction_sep}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+    )
+}
+
+pub fn to_srt(cues: &[Cue]) -> String {
+    cues.iter()
+        .enumerate()
+        .fold(String::new(), |acc, (n, cue)| {
+            format!(
+                "{acc}{}\n{} --> {}\n{}\n\n",
+                n + 1,
+                timestamp(cue.start, ','),
+                timestamp(cue.end, ','),
+                cue.lines.join("\n"),
+            )
+        })
+}
+
+/// `&` and `<` start character references and tags in WebVTT, and `-->` is not allowed in cue text
+fn vtt_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace("-->", "--&gt;")
+}
+
+pub fn to_vtt(cues: &[Cue]) -> String {
+    cues.iter().fold(String::from("WEBVTT\n\n"), |acc, cue| {
+        format!(
+            "{acc}{} --> {}\n{}\n\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            vtt_escape(&cue.lines.join("\n")),
+        )
+    })
+}
+
+/// Write `output.srt` and `output.vtt` into the current (project root) directory.
+pub fn write(env: &Environment, events: &[Event<Sound, Sound, Clip>]) -> anyhow::Result<()> {
+    if !env.subtitle_enabled() {
+        return Ok(());
+    }
+
+    let layout = Layout {
+        max_line_chars: env.subtitle_max_line_chars(),
+        max_lines: env.subtitle_max_lines(),
+        max_duration: env.subtitle_max_duration(),
+    };
+
+    let cues = cues(layout, events);
+
+    std::fs::write("output.srt", to_srt(&cues)).with_context(|| "Failed to write output.srt")?;
+    std::fs::write("output.vtt", to_vtt(&cues)).with_context(|| "Failed to write output.vtt")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: Layout = Layout {
+        max_line_chars: 10,
+        max_lines: 2,
+        max_duration: 7.0,
+    };
+
+    #[test]
+    fn wrap_breaks_on_whitespace() {
+        assert_eq!(wrap("the quick brown fox", 10), ["the quick", "brown fox"]);
+    }
+
+    #[test]
+    fn wrap_splits_long_word_after_full_line() {
+        assert_eq!(wrap("abcde fghijklmn", 5), ["abcde", "fghij", "klmn"]);
+        assert_eq!(wrap("abcd fghijklmn", 5), ["abcd", "fghij", "klmn"]);
+    }
+
+    #[test]
+    fn wrap_fills_line_with_long_word() {
+        assert_eq!(wrap("ab cdefghij", 5), ["ab cd", "efghi", "j"]);
+    }
+
+    #[test]
+    fn wrap_cjk_without_spaces() {
+        assert_eq!(
+            wrap("吾輩は猫である名前はまだ無い", 5),
+            ["吾輩は猫で", "ある名前は", "まだ無い"]
+        );
+    }
+
+    #[test]
+    fn wrap_empty() {
+        assert!(wrap("   ", 5).is_empty());
+    }
+
+    #[test]
+    fn split_cue_keeps_short_text_in_one_cue() {
+        let cues = split_cue(LAYOUT, 1.0, 2.0, "hello world");
+
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].lines, ["hello", "world"]);
+        assert_eq!((cues[0].start, cues[0].end), (1.0, 3.0));
+    }
+
+    #[test]
+    fn split_cue_splits_by_max_lines_and_duration() {
+        let text = "吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。";
+        let cues = split_cue(LAYOUT, 0.0, 10.0, text);
+
+        assert!(cues.len() > 1);
+        assert!(cues.iter().all(|cue| cue.lines.len() <= LAYOUT.max_lines));
+        assert_eq!(cues.first().unwrap().start, 0.0);
+        assert!((cues.last().unwrap().end - 10.0).abs() < 1e-9);
+        assert!(cues.windows(2).all(|w| w[0].end == w[1].start));
+        assert_eq!(
+            cues.iter()
+                .map(|cue| cue.lines.concat())
+                .collect::<String>(),
+            text
+        );
+    }
+
+    #[test]
+    fn split_cue_long_word_after_full_line() {
+        let cues = split_cue(
+            LAYOUT,
+            0.0,
+            1.0,
+            "abcdefghij https://example.com/a/long/path",
+        );
+        let lines: Vec<_> = cues.iter().flat_map(|cue| cue.lines.clone()).collect();
+
+        assert_eq!(
+            lines,
+            ["abcdefghij", "https://ex", "ample.com/", "a/long/pat", "h"]
+        );
+    }
+
+    #[test]
+    fn vtt_escapes_cue_text() {
+        let cues = [Cue {
+            start: 0.0,
+            end: 1.0,
+            lines: vec!["R&D <b>".to_string(), "a --> b".to_string()],
+        }];
+
+        assert_eq!(
+            to_vtt(&cues),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nR&amp;D &lt;b>\na --&gt; b\n\n"
+        );
+    }
+}
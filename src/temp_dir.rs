@@ -0,0 +1,38 @@
+//! Temporary directories of the tests
+use std::path::{Path, PathBuf};
+
+/// A directory under the system temp dir, removed with its files when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` must be unique among the tests, which run in parallel
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("marp-video-{}-{name}", std::process::id()));
+
+        // Left by an aborted run
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Write the file (and its parent directories), returning its path
+    pub fn write(&self, file: &str, content: &str) -> PathBuf {
+        let path = self.0.join(file);
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}